@@ -0,0 +1,140 @@
+//! Compares ways of restricting a search to some seasons: a `FilterCollector` over the `season` fast field,
+//! which is how `Db::search` used to do it, a `TermSetQuery` on the indexed field, and the union of term
+//! queries that `SearchFilters` builds now.
+
+use std::cmp::Reverse;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use tantivy::{
+    collector::{Collector, FilterCollector, TopDocs},
+    query::{BooleanQuery, Occur, Query, TermQuery, TermSetQuery},
+    schema::IndexRecordOption,
+    DocAddress, DocId, Document, Index, IndexReader, SegmentReader, Term,
+};
+
+use curiosity::{filters::SearchFilters, SeasonId};
+
+const SEASONS: u64 = 13;
+const EPISODES_PER_SEASON: u64 = 400;
+const PAGE_SIZE: usize = 50;
+
+fn build_index() -> (Index, IndexReader) {
+    let index = Index::create_in_ram(curiosity::schema::build_schema());
+    let schema = index.schema();
+    let mut writer = index.writer(50_000_000).unwrap();
+
+    for season in 0..SEASONS {
+        for episode in 0..EPISODES_PER_SEASON {
+            let mut doc = Document::new();
+            doc.add_u64(
+                schema.get_field("episode_id").unwrap(),
+                (season << 32) | episode,
+            );
+            doc.add_u64(schema.get_field("season").unwrap(), season);
+            doc.add_u64(schema.get_field("sorting_number").unwrap(), episode);
+            doc.add_text(
+                schema.get_field("title").unwrap(),
+                format!("episode {episode}"),
+            );
+            // every episode matches the query, so the season filter does all the work
+            doc.add_text(
+                schema.get_field("body").unwrap(),
+                format!("the dice are rolled and the story goes on. episode {episode} of season {season}."),
+            );
+            writer.add_document(doc).unwrap();
+        }
+    }
+
+    writer.commit().unwrap();
+    let reader = index.reader().unwrap();
+    (index, reader)
+}
+
+fn top_docs(index: &Index) -> impl Collector<Fruit = Vec<(Reverse<u64>, DocAddress)>> {
+    let episode_id_field = index.schema().get_field("episode_id").unwrap();
+    TopDocs::with_limit(PAGE_SIZE).custom_score(move |segment_reader: &SegmentReader| {
+        let episode_reader = segment_reader.fast_fields().u64(episode_id_field).unwrap();
+        move |doc: DocId| Reverse(episode_reader.get_val(doc))
+    })
+}
+
+fn season_filter(c: &mut Criterion) {
+    let (index, reader) = build_index();
+    let schema = index.schema();
+    let searcher = reader.searcher();
+    let season_field = schema.get_field("season").unwrap();
+
+    let body_query = TermQuery::new(
+        Term::from_field_text(schema.get_field("body").unwrap(), "the"),
+        IndexRecordOption::Basic,
+    );
+
+    let mut group = c.benchmark_group("season_filter");
+
+    for seasons in [
+        vec![SeasonId::Partizan],
+        vec![SeasonId::Marielda, SeasonId::Sangfielle, SeasonId::Patreon],
+    ] {
+        let count = seasons.len();
+
+        group.bench_with_input(
+            BenchmarkId::new("filter_collector", count),
+            &seasons,
+            |b, seasons| {
+                b.iter(|| {
+                    let seasons = seasons.clone();
+                    let collector = FilterCollector::new(
+                        season_field,
+                        move |season: u64| seasons.iter().any(|s| *s as u64 == season),
+                        top_docs(&index),
+                    );
+                    black_box(searcher.search(&body_query, &collector).unwrap())
+                })
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("term_set_query", count),
+            &seasons,
+            |b, seasons| {
+                b.iter(|| {
+                    let query = BooleanQuery::new(vec![
+                        (Occur::Must, Box::new(body_query.clone())),
+                        (
+                            Occur::Must,
+                            Box::new(TermSetQuery::new(
+                                seasons
+                                    .iter()
+                                    .map(|s| Term::from_field_u64(season_field, *s as u64)),
+                            )),
+                        ),
+                    ]);
+                    black_box(searcher.search(&query, &top_docs(&index)).unwrap())
+                })
+            },
+        );
+
+        let filters = SearchFilters {
+            seasons: seasons.iter().copied().collect(),
+            ..Default::default()
+        };
+        group.bench_with_input(
+            BenchmarkId::new("search_filters", count),
+            &filters,
+            |b, filters| {
+                b.iter(|| {
+                    let mut clauses: Vec<(Occur, Box<dyn Query>)> =
+                        vec![(Occur::Must, Box::new(body_query.clone()))];
+                    clauses.extend(filters.clauses(&schema));
+                    let query = BooleanQuery::new(clauses);
+                    black_box(searcher.search(&query, &top_docs(&index)).unwrap())
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, season_filter);
+criterion_main!(benches);
@@ -1,27 +1,35 @@
 use std::{
     cmp::Reverse,
     collections::{BTreeMap, HashMap, HashSet},
-    io::Read,
+    io::{Read, Seek, Write},
+    ops::Bound,
     path::{Path, PathBuf},
     sync::Arc,
 };
 
-use parking_lot::RwLock;
-use redb::{Table, TableDefinition};
+use parking_lot::{Mutex, RwLock};
+use redb::{ReadableTable, Table};
 use smallvec::SmallVec;
 
 use tantivy::{
-    collector::{FilterCollector, TopDocs},
+    collector::TopDocs,
     directory::MmapDirectory,
-    query::{PhraseQuery, Query, QueryParser, TermSetQuery},
+    query::{
+        BooleanQuery, Occur, PhraseQuery, Query, QueryParser, RangeQuery, TermQuery, TermSetQuery,
+    },
+    schema::IndexRecordOption,
     store::Compressor,
     tokenizer::TextAnalyzer,
-    DocAddress, DocId, Document, Index, IndexReader, IndexSettings, SegmentReader, Term,
+    DocId, Document, Index, IndexReader, IndexSettings, SegmentReader, TantivyError, Term,
 };
 
 use crate::{
+    filters::SearchFilters,
+    report::IngestionReport,
     sentence::Sentence,
-    store::{SentenceList, Store, TermsToSentencesId},
+    snapshot::{write_snapshot, SnapshotManifest},
+    stop_words::StopWords,
+    store::{SentenceList, Store, TermsToSentencesId, FORMAT_VERSIONS, GENERATION_KEY},
     term_map::TermMap,
     CuriosityError, CuriosityResult, Episode, Season, SeasonId, StoredEpisode,
 };
@@ -35,11 +43,35 @@ pub struct Db {
     tokenizer: TextAnalyzer,
     term_map: Arc<RwLock<TermMap>>,
     terms_path: PathBuf,
+    folder: PathBuf,
+    stop_words: Arc<StopWords>,
+    /// held while the files on disk change, so snapshots never see half an update
+    write_lock: Arc<Mutex<()>>,
+}
+
+/// Bytes on disk used by each part of a [`Db`].
+#[derive(Debug, Clone, Copy)]
+pub struct DiskUsage {
+    pub store: u64,
+    pub index: u64,
+    pub terms: u64,
 }
 
 pub struct QueryWithTerms<T: Query> {
     pub query: T,
     pub terms: SmallVec<[u32; 8]>,
+    /// How many distinct terms the query looks for, including ones that aren't in the term map.
+    pub term_count: usize,
+}
+
+pub type BoxedQuery = QueryWithTerms<Box<dyn Query>>;
+
+/// An episode found by [`Db::search`].
+#[derive(Clone, Copy, Debug)]
+pub struct SearchHit {
+    pub episode_id: u64,
+    /// Where the episode sorts, see [`crate::episode_ids::position`]. Searches resume after one of these.
+    pub position: u64,
 }
 
 impl<T: Query> QueryWithTerms<T> {
@@ -47,10 +79,27 @@ impl<T: Query> QueryWithTerms<T> {
         QueryWithTerms {
             query: Box::new(self.query),
             terms: self.terms,
+            term_count: self.term_count,
         }
     }
 }
 
+fn distinct_count(terms: &[Term]) -> usize {
+    terms.iter().collect::<HashSet<_>>().len()
+}
+
+fn open_index(index_path: &Path) -> CuriosityResult<Index> {
+    std::fs::create_dir_all(index_path)?;
+
+    Ok(Index::builder()
+        .schema(crate::schema::build_schema())
+        .settings(IndexSettings {
+            docstore_compression: Compressor::None,
+            ..Default::default()
+        })
+        .open_or_create(MmapDirectory::open(index_path)?)?)
+}
+
 impl Db {
     pub fn new(folder: impl AsRef<Path>) -> CuriosityResult<Db> {
         let folder = folder.as_ref();
@@ -59,24 +108,38 @@ impl Db {
         let store_path = folder.join("store.redb");
         let terms_path = folder.join("terms.postcard");
 
-        std::fs::create_dir_all(&index_path)?;
-
-        let index = Index::builder()
-            .schema(crate::schema::build_schema())
-            .settings(IndexSettings {
-                docstore_compression: Compressor::None,
-                ..Default::default()
-            })
-            .open_or_create(MmapDirectory::open(index_path)?)?;
-
-        let store_env = redb::Database::builder()
-            .set_cache_size(1_000_000_000)
-            .create(store_path)?;
+        std::fs::create_dir_all(folder)?;
+        let dbs = Store::open(store_path)?;
+
+        // there's no migrating documents in an old layout, but they're all rebuilt on the next update anyway
+        let outdated = dbs.outdated_formats()?;
+        if !outdated.is_empty() {
+            tracing::warn!(
+                formats = ?outdated,
+                "stored documents are in an outdated format, discarding them until the next update"
+            );
+            dbs.discard_documents()?;
+            if terms_path.exists() {
+                std::fs::remove_file(&terms_path)?;
+            }
+            if index_path.exists() {
+                std::fs::remove_dir_all(&index_path)?;
+            }
+        }
 
-        let dbs = Store {
-            db: Arc::new(store_env),
-            docs: TableDefinition::new("docs"),
-            terms_to_sentences: TableDefinition::new("terms_to_sentences"),
+        let index = match open_index(&index_path) {
+            // the index is rebuilt from scratch on every update anyway, so an outdated one can just go.
+            // the documents go with it, which also forgets the source, so the next update can't skip an unchanged archive
+            Err(CuriosityError::Tantivy(TantivyError::SchemaError(e))) => {
+                tracing::warn!(path = %index_path.display(), error = %e, "discarding index");
+                dbs.discard_documents()?;
+                if terms_path.exists() {
+                    std::fs::remove_file(&terms_path)?;
+                }
+                std::fs::remove_dir_all(&index_path)?;
+                open_index(&index_path)?
+            }
+            index => index?,
         };
 
         let term_map = if let Ok(mut terms_file) = std::fs::File::open(&terms_path) {
@@ -86,7 +149,7 @@ impl Db {
 
             let map: TermMap = postcard::from_bytes(&bytes)?;
 
-            println!("loaded {} terms from {}", map.len(), terms_path.display());
+            tracing::info!(count = map.len(), path = %terms_path.display(), "loaded terms");
 
             Arc::new(RwLock::new(map))
         } else {
@@ -112,15 +175,36 @@ impl Db {
             parser,
             term_map,
             terms_path,
+            folder: folder.to_owned(),
+            stop_words: Arc::new(StopWords::default()),
+            write_lock: Arc::default(),
         })
     }
 
-    pub fn add_documents<'a, I, F>(&self, seasons: I, mut read_document: F) -> CuriosityResult<()>
+    /// Rebuilds the index from `seasons`. Episodes that can't be read or assigned an id are left out
+    /// and listed in the returned report, which is also persisted alongside the documents.
+    /// If `read_document` returns [`CuriosityError::Cancelled`], the previous index and store are kept as they were.
+    pub fn add_documents<'a, I, F>(
+        &self,
+        seasons: I,
+        mut read_document: F,
+    ) -> CuriosityResult<IngestionReport>
     where
         I: IntoIterator<Item = &'a Season>,
         F: FnMut(SeasonId, &Episode) -> CuriosityResult<String>,
     {
+        let _span = tracing::info_span!("add_documents").entered();
+        let _write_guard = self.write_lock.lock();
+        let seasons: Vec<&Season> = seasons.into_iter().collect();
+        let mut report = IngestionReport::start();
+
         let txn = self.store.begin_write()?;
+
+        let episode_ids = {
+            let mut ids_db: Table<&str, u64> = txn.open_table(self.store.episode_ids)?;
+            crate::episode_ids::assign_ids(&mut ids_db, &seasons)?
+        };
+
         txn.delete_table(self.store.docs)?;
         txn.delete_table(self.store.terms_to_sentences)?;
 
@@ -139,19 +223,40 @@ impl Db {
         let mut term_map = HashMap::new();
         term_map.insert(" ".to_owned(), 0);
 
-        let mut authors = HashSet::new();
-
         for season in seasons {
-            for episode in season.episodes.iter() {
-                if episode.download.is_none() {
+            for (idx, episode) in season.episodes.iter().enumerate() {
+                let Some(download) = episode.download.as_ref() else {
+                    report.skip(season.id, episode, "no plain text download");
                     continue;
-                }
+                };
 
-                let ep_id = (season.id as u64 * 1000) + episode.sorting_number as u64;
+                let ep_id = match &episode_ids[&(season.id, idx)] {
+                    Ok(id) => *id,
+                    Err(clash) => {
+                        report.fail(season.id, episode, clash);
+                        continue;
+                    }
+                };
 
-                let episode_text = read_document(season.id, episode)?;
+                let episode_text = match read_document(season.id, episode) {
+                    Ok(text) => text,
+                    Err(CuriosityError::Cancelled) => return Err(CuriosityError::Cancelled),
+                    Err(e) => {
+                        report.fail(season.id, episode, e);
+                        continue;
+                    }
+                };
+
+                let turns = download.format(&episode_text).parser().parse(&episode_text);
 
-                let sentences = Sentence::tokenize(&episode_text, &tokenizer, &mut term_map)?;
+                let sentences =
+                    match Sentence::tokenize(&episode_text, &turns, &tokenizer, &mut term_map) {
+                        Ok(sentences) => sentences,
+                        Err(e) => {
+                            report.fail(season.id, episode, e);
+                            continue;
+                        }
+                    };
 
                 let stored_doc = StoredEpisode {
                     id: ep_id,
@@ -168,13 +273,14 @@ impl Db {
 
                 // term -> (sentence_ids)[]
                 let mut term_to_sentence_mapping: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+                let mut authors = HashSet::new();
 
                 for (idx, sentence) in stored_doc.tokens.iter().enumerate() {
                     authors.insert(sentence.author);
                     for token in &sentence.tokens_by_position {
                         term_to_sentence_mapping
                             .entry(token.term)
-                            .or_insert_with(Vec::new)
+                            .or_default()
                             .push(idx as u32);
                     }
                 }
@@ -188,11 +294,30 @@ impl Db {
 
                 let mut doc = Document::new();
                 doc.add_u64(schema.get_field("episode_id").unwrap(), ep_id);
+                // assign_ids only hands out ids to episodes with a position of their own
+                let position = crate::episode_ids::position(season.id, episode.sorting_number)
+                    .expect("episodes with an id have a position");
+                doc.add_u64(schema.get_field("position").unwrap(), position);
                 doc.add_text(schema.get_field("title").unwrap(), episode.title.as_str());
                 doc.add_u64(schema.get_field("season").unwrap(), season.id as u64);
+                doc.add_u64(
+                    schema.get_field("sorting_number").unwrap(),
+                    episode.sorting_number as u64,
+                );
                 doc.add_text(schema.get_field("body").unwrap(), episode_text.clone());
+                for author in authors {
+                    doc.add_u64(schema.get_field("speakers").unwrap(), author as u64);
+                }
 
                 index_writer.add_document(doc)?;
+                report.indexed += 1;
+
+                tracing::info!(
+                    season = %season.id,
+                    slug = %episode.slug,
+                    sentences = stored_doc.tokens.len(),
+                    "indexed episode"
+                );
             }
         }
 
@@ -201,30 +326,57 @@ impl Db {
         drop(terms_to_sentences_db);
         drop(doc_db);
 
+        report.finish();
+
+        let mut reports_db: Table<u64, &[u8]> = txn.open_table(self.store.reports)?;
+        reports_db.insert(report.started_at, postcard::to_stdvec(&report)?.as_slice())?;
+        drop(reports_db);
+
+        let mut meta_db: Table<&str, u64> = txn.open_table(self.store.meta)?;
+        let generation = meta_db.get(GENERATION_KEY)?.map_or(0, |g| g.value());
+        meta_db.insert(GENERATION_KEY, generation + 1)?;
+        for (key, version) in FORMAT_VERSIONS {
+            meta_db.insert(*key, *version)?;
+        }
+        drop(meta_db);
+
         txn.commit()?;
         index_writer.commit()?;
 
-        Ok(())
+        Ok(report)
+    }
+
+    /// Leaves stop words out of the highlighted terms, so matching lines aren't lit up by every "the" and "of".
+    /// Phrase queries keep them, since they're part of the phrase.
+    pub fn with_stop_words(mut self, stop_words: StopWords) -> Db {
+        self.stop_words = Arc::new(stop_words);
+        self
     }
 
     pub fn parse_query(&self, query: &str) -> CuriosityResult<QueryWithTerms<impl Query>> {
         let query = self.parser.parse_query(query)?;
         let body_field = self.index.schema().get_field("body").unwrap();
-        let mut terms = SmallVec::new();
-        let term_map = self.term_map.read();
+        let mut tokens = Vec::new();
+        // the same terms are searched for in several fields, so each one only counts once
+        let mut distinct: HashSet<Vec<u8>> = HashSet::new();
         query.query_terms(&mut |term: &tantivy::Term, _| {
+            distinct.insert(term.value_bytes().to_vec());
             if term.field() != body_field {
                 return;
             }
 
-            if let Some(term) = term.as_str().and_then(|text| term_map.get(text)) {
-                terms.push(term);
+            if let Some(text) = term.as_str() {
+                tokens.push(text.to_owned());
             }
         });
 
-        terms.sort_unstable();
+        let terms = self.resolve_keywords(tokens.iter().map(String::as_str));
 
-        Ok(QueryWithTerms { query, terms })
+        Ok(QueryWithTerms {
+            query,
+            terms,
+            term_count: distinct.len(),
+        })
     }
 
     pub fn phrase_query(&self, query: &str) -> QueryWithTerms<impl Query> {
@@ -245,6 +397,7 @@ impl Db {
         }
 
         QueryWithTerms {
+            term_count: distinct_count(&out),
             query: PhraseQuery::new(out),
             terms: term_set,
         }
@@ -254,74 +407,164 @@ impl Db {
         let mut stream = self.tokenizer.token_stream(query);
         let mut out = Vec::with_capacity(query.len());
         let field = self.index.schema().get_field("body").unwrap();
-        let term_map = self.term_map.read();
 
         while let Some(tok) = stream.next() {
             out.push(Term::from_field_text(field, tok.text.as_str()));
         }
 
-        let mut term_set = SmallVec::new();
-        for term in out.iter() {
-            if let Some(term) = term.as_str().and_then(|text| term_map.get(text)) {
-                term_set.push(term);
-            }
-        }
+        let term_set = self.resolve_keywords(out.iter().filter_map(|term| term.as_str()));
 
         QueryWithTerms {
+            term_count: distinct_count(&out),
             query: TermSetQuery::new(out),
             terms: term_set,
         }
     }
 
+    /// Looks up the ids of keyword tokens, skipping stop words unless there's nothing but stop words.
+    fn resolve_keywords<'t>(&self, tokens: impl Iterator<Item = &'t str>) -> SmallVec<[u32; 8]> {
+        let term_map = self.term_map.read();
+        let (mut keywords, mut stop_words) = (SmallVec::new(), SmallVec::new());
+
+        for token in tokens {
+            let Some(term) = term_map.get(token) else {
+                continue;
+            };
+
+            if self.stop_words.contains(token) {
+                stop_words.push(term);
+            } else {
+                keywords.push(term);
+            }
+        }
+
+        let mut terms = if keywords.is_empty() {
+            stop_words
+        } else {
+            keywords
+        };
+        terms.sort_unstable();
+        terms
+    }
+
+    /// Finds up to `page_size` episodes matching `query` and `filters` in season, then sorting number order,
+    /// starting after the episode at position `after`.
     pub fn search(
         &self,
         query: &QueryWithTerms<impl Query>,
-        filter_seasons: SmallVec<[SeasonId; 16]>,
+        filters: &SearchFilters,
         page_size: usize,
-        offset: usize,
-    ) -> CuriosityResult<Vec<(Reverse<u64>, DocAddress)>> {
-        let episode_id_field = self.index.schema().get_field("episode_id").unwrap();
-        let season_id_field = self.index.schema().get_field("season").unwrap();
+        after: Option<u64>,
+    ) -> CuriosityResult<Vec<SearchHit>> {
+        let schema = self.index.schema();
+        let episode_id_field = schema.get_field("episode_id").unwrap();
+        let position_field = schema.get_field("position").unwrap();
 
         let searcher = self.reader.searcher();
 
-        if !filter_seasons.is_empty() {
-            searcher
-                .search(
-                    &query.query,
-                    &FilterCollector::new(
-                        season_id_field,
-                        move |season: u64| {
-                            let season_id: SeasonId = unsafe { std::mem::transmute(season) };
-
-                            filter_seasons.contains(&season_id)
-                        },
-                        TopDocs::with_limit(page_size)
-                            .and_offset(offset)
-                            .custom_score(move |segment_reader: &SegmentReader| {
-                                let episode_reader =
-                                    segment_reader.fast_fields().u64(episode_id_field).unwrap();
-
-                                move |doc: DocId| Reverse(episode_reader.get_val(doc))
-                            }),
-                    ),
-                )
-                .map_err(CuriosityError::Tantivy)
+        // filters and resuming are queries on indexed fields, so tantivy skips
+        // whatever they rule out instead of scoring it and throwing it away
+        let mut clauses = filters.clauses(&schema);
+        if let Some(after) = after {
+            clauses.push((
+                Occur::Must,
+                Box::new(RangeQuery::new_u64_bounds(
+                    position_field,
+                    Bound::Excluded(after),
+                    Bound::Unbounded,
+                )),
+            ));
+        }
+
+        let query: Box<dyn Query> = if clauses.is_empty() {
+            query.query.box_clone()
         } else {
-            searcher
-                .search(
-                    &query.query,
-                    &TopDocs::with_limit(page_size)
-                        .and_offset(offset)
-                        .custom_score(move |segment_reader: &SegmentReader| {
-                            let episode_reader =
-                                segment_reader.fast_fields().u64(episode_id_field).unwrap();
-
-                            move |doc: DocId| Reverse(episode_reader.get_val(doc))
-                        }),
-                )
-                .map_err(CuriosityError::Tantivy)
+            clauses.insert(0, (Occur::Must, query.query.box_clone()));
+            Box::new(BooleanQuery::new(clauses))
+        };
+
+        // positions are unique, the id just comes along for the ride
+        let top_docs =
+            TopDocs::with_limit(page_size).custom_score(move |segment_reader: &SegmentReader| {
+                let fast_fields = segment_reader.fast_fields();
+                let position_reader = fast_fields.u64(position_field).unwrap();
+                let episode_reader = fast_fields.u64(episode_id_field).unwrap();
+
+                move |doc: DocId| {
+                    Reverse((position_reader.get_val(doc), episode_reader.get_val(doc)))
+                }
+            });
+
+        let hits = searcher
+            .search(&query, &top_docs)
+            .map_err(CuriosityError::Tantivy)?;
+
+        Ok(hits
+            .into_iter()
+            .map(|(Reverse((position, episode_id)), _)| SearchHit {
+                episode_id,
+                position,
+            })
+            .collect())
+    }
+
+    /// The season and sorting number of `episode_id`, if it's in the index.
+    pub fn episode_position(&self, episode_id: u64) -> CuriosityResult<Option<(u64, u64)>> {
+        let schema = self.index.schema();
+        let position_field = schema.get_field("position").unwrap();
+
+        let query = TermQuery::new(
+            Term::from_field_u64(schema.get_field("episode_id").unwrap(), episode_id),
+            IndexRecordOption::Basic,
+        );
+        let top_docs =
+            TopDocs::with_limit(1).custom_score(move |segment_reader: &SegmentReader| {
+                let position_reader = segment_reader.fast_fields().u64(position_field).unwrap();
+                move |doc: DocId| position_reader.get_val(doc)
+            });
+
+        let hits = self
+            .reader
+            .searcher()
+            .search(&query, &top_docs)
+            .map_err(CuriosityError::Tantivy)?;
+
+        // see episode_ids::position
+        Ok(hits
+            .first()
+            .map(|&(position, _)| (position >> 32, position & u64::from(u32::MAX))))
+    }
+
+    /// Episodes in the index as of the last reload.
+    pub fn num_docs(&self) -> u64 {
+        self.reader.searcher().num_docs()
+    }
+
+    pub fn disk_usage(&self) -> CuriosityResult<DiskUsage> {
+        let mut index = 0;
+        for entry in std::fs::read_dir(self.folder.join("index"))? {
+            let metadata = entry?.metadata()?;
+            if metadata.is_file() {
+                index += metadata.len();
+            }
         }
+
+        Ok(DiskUsage {
+            store: std::fs::metadata(self.folder.join("store.redb"))?.len(),
+            index,
+            terms: std::fs::metadata(&self.terms_path).map_or(0, |m| m.len()),
+        })
+    }
+
+    /// Writes a snapshot archive of everything on disk to `out`, waiting for any update in progress to finish first.
+    pub fn snapshot<W: Write + Seek>(&self, out: W) -> CuriosityResult<SnapshotManifest> {
+        let _write_guard = self.write_lock.lock();
+        // nothing else can write to the store while this transaction is open
+        let txn = self.store.begin_write()?;
+        let manifest = write_snapshot(&self.folder, self.store.generation()?, out)?;
+        txn.abort()?;
+
+        Ok(manifest)
     }
 
     pub fn replace_term_map(&self, new_map: HashMap<String, u32>) -> CuriosityResult<()> {
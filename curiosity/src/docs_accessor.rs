@@ -1,6 +1,12 @@
+use std::sync::Arc;
+
+use parking_lot::Mutex;
 use redb::{AccessGuard, ReadOnlyTable, ReadableTable};
+use rkyv::AlignedVec;
 
-use crate::{ArchivedStoredEpisode, CuriosityError, CuriosityResult, StoredEpisode};
+use crate::{
+    store::ValidatedDocs, ArchivedStoredEpisode, CuriosityError, CuriosityResult, StoredEpisode,
+};
 
 pub trait DocsAccessor {
     type Target<'a>: DocumentGuard
@@ -16,33 +22,87 @@ pub trait DocumentGuard {
 
     fn try_read_doc(&mut self) -> CuriosityResult<Self::Target<'_>>;
 
-    /// Panicking version of try_read_doc
+    /// Panicking version of try_read_doc. Prefer try_read_doc wherever the bytes come from disk
     fn read_doc(&mut self) -> Self::Target<'_>;
 }
 
 pub struct SimpleDocsAccessor<'txn> {
     pub(crate) docs: ReadOnlyTable<'txn, u64, &'static [u8]>,
+    /// generation of the store as this transaction sees it
+    pub(crate) generation: u64,
+    pub(crate) validated: Arc<Mutex<ValidatedDocs>>,
+}
+
+impl SimpleDocsAccessor<'_> {
+    /// Generation of the store as this transaction sees it, to tell whether it still matches the search
+    /// results it's reading documents for.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
 }
 
 impl<'txn> DocsAccessor for SimpleDocsAccessor<'txn> {
-    type Target<'a> = AccessGuard<'a, &'a [u8]> where Self: 'a;
+    type Target<'a> = StoredDocGuard<'a> where Self: 'a;
 
     #[inline(always)]
-    fn get_doc(&mut self, doc: u64) -> CuriosityResult<AccessGuard<'_, &[u8]>> {
-        self.docs.get(doc)?.ok_or(CuriosityError::NotFound)
+    fn get_doc(&mut self, doc: u64) -> CuriosityResult<StoredDocGuard<'_>> {
+        Ok(StoredDocGuard {
+            id: doc,
+            guard: self.docs.get(doc)?.ok_or(CuriosityError::NotFound)?,
+            aligned: None,
+            checked: false,
+            generation: self.generation,
+            validated: &self.validated,
+        })
+    }
+}
+
+/// A stored episode, validated the first time it's read in its store generation.
+pub struct StoredDocGuard<'a> {
+    id: u64,
+    guard: AccessGuard<'a, &'a [u8]>,
+    /// copy of the bytes, if redb handed them out at an address rkyv can't read them from
+    aligned: Option<AlignedVec>,
+    checked: bool,
+    generation: u64,
+    validated: &'a Mutex<ValidatedDocs>,
+}
+
+impl StoredDocGuard<'_> {
+    fn bytes(&self) -> &[u8] {
+        match &self.aligned {
+            Some(aligned) => aligned.as_slice(),
+            None => self.guard.value(),
+        }
     }
 }
 
-impl<'a> DocumentGuard for AccessGuard<'a, &'a [u8]> {
+impl<'a> DocumentGuard for StoredDocGuard<'a> {
     type Target<'item>  = &'item ArchivedStoredEpisode where Self: 'item;
 
-    #[inline(always)]
     fn try_read_doc(&mut self) -> CuriosityResult<Self::Target<'_>> {
-        Ok(self.read_doc())
+        if !self.checked {
+            let bytes = self.guard.value();
+            if bytes.as_ptr().align_offset(std::mem::align_of::<ArchivedStoredEpisode>()) != 0 {
+                let mut aligned = AlignedVec::with_capacity(bytes.len());
+                aligned.extend_from_slice(bytes);
+                self.aligned = Some(aligned);
+            }
+
+            if !self.validated.lock().contains(self.generation, self.id) {
+                rkyv::check_archived_root::<StoredEpisode>(self.bytes())
+                    .map_err(|e| CuriosityError::InvalidDocument(self.id, e.to_string()))?;
+                self.validated.lock().insert(self.generation, self.id);
+            }
+            self.checked = true;
+        }
+
+        // safe: checked above or earlier in this generation, and the bytes can't change while the guard is alive
+        Ok(unsafe { rkyv::archived_root::<StoredEpisode>(self.bytes()) })
     }
 
     #[inline(always)]
     fn read_doc(&mut self) -> Self::Target<'_> {
-        unsafe { rkyv::archived_root::<StoredEpisode>(self.value()) }
+        self.try_read_doc().unwrap()
     }
 }
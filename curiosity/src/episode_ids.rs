@@ -0,0 +1,201 @@
+use std::collections::{HashMap, HashSet};
+
+use redb::{ReadableTable, Table};
+
+use crate::{CuriosityResult, Episode, Season, SeasonId};
+
+/// Where an episode sorts: the season in the upper 32 bits and the sorting number in the lower 32,
+/// so ordering by position is ordering by season, then by episode. Search results are ordered by this.
+pub fn position(season: SeasonId, sorting_number: usize) -> Option<u64> {
+    let sorting_number = u32::try_from(sorting_number).ok()?;
+    Some(((season as u64) << 32) | sorting_number as u64)
+}
+
+/// The id a new episode gets unless it's taken: its [`position`] when it first showed up.
+/// Ids stay put when episodes are renumbered later, so they say nothing about order.
+pub fn candidate_id(season: SeasonId, sorting_number: usize) -> Option<u64> {
+    position(season, sorting_number)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EpisodeRef {
+    pub season: SeasonId,
+    pub slug: String,
+    pub title: String,
+    pub sorting_number: usize,
+}
+
+impl EpisodeRef {
+    pub fn new(season: SeasonId, episode: &Episode) -> EpisodeRef {
+        EpisodeRef {
+            season,
+            slug: episode.slug.clone(),
+            title: episode.title.clone(),
+            sorting_number: episode.sorting_number,
+        }
+    }
+}
+
+impl std::fmt::Display for EpisodeRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} #{} '{}' ({})",
+            self.season, self.sorting_number, self.title, self.slug
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum IdClash {
+    DuplicateSlug {
+        first: EpisodeRef,
+        second: EpisodeRef,
+    },
+    DuplicateId {
+        id: u64,
+        first: EpisodeRef,
+        second: EpisodeRef,
+    },
+    Overflow(EpisodeRef),
+}
+
+impl std::fmt::Display for IdClash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IdClash::DuplicateSlug { first, second } => {
+                write!(f, "slug used twice: {first} and {second}")
+            }
+            IdClash::DuplicateId { id, first, second } => {
+                write!(f, "id {id} claimed by both {first} and {second}")
+            }
+            IdClash::Overflow(episode) => write!(f, "sorting number out of range: {episode}"),
+        }
+    }
+}
+
+/// Outcome of id assignment for every downloadable episode, keyed by season and position in that season.
+pub type IdAssignment = HashMap<(SeasonId, usize), Result<u64, IdClash>>;
+
+/// Resolves the id of every downloadable episode, reusing the id persisted for its slug if there is one.
+/// New slugs get their candidate id, which is then persisted so later renumbering in seasons.json can't move them.
+/// The persisted ids of every slug still in seasons.json are reserved up front, downloadable or not, so a new slug
+/// whose candidate is taken gets the next free id in its season instead.
+/// Episodes that clash with an earlier one, by slug or by position, get the clash instead of an id.
+pub fn assign_ids(
+    ids_db: &mut Table<&str, u64>,
+    seasons: &[&Season],
+) -> CuriosityResult<IdAssignment> {
+    let mut persisted: HashMap<String, u64> = HashMap::new();
+    for entry in ids_db.iter()? {
+        let (slug, id) = entry?;
+        persisted.insert(slug.value().to_owned(), id.value());
+    }
+
+    let reserved: HashSet<u64> = seasons
+        .iter()
+        .flat_map(|season| season.episodes.iter())
+        .filter_map(|episode| persisted.get(&episode.slug).copied())
+        .collect();
+
+    let mut assignment = IdAssignment::new();
+    let mut by_slug: HashMap<&str, EpisodeRef> = HashMap::new();
+    let mut by_position: HashMap<u64, EpisodeRef> = HashMap::new();
+    let mut by_id: HashMap<u64, EpisodeRef> = HashMap::new();
+    let mut assigned: HashMap<&str, u64> = HashMap::new();
+
+    for season in seasons {
+        for (idx, episode) in season.episodes.iter().enumerate() {
+            if episode.download.is_none() {
+                continue;
+            }
+
+            let episode_ref = EpisodeRef::new(season.id, episode);
+            let resolved = if let Some(first) = by_slug.get(episode.slug.as_str()) {
+                Err(IdClash::DuplicateSlug {
+                    first: first.clone(),
+                    second: episode_ref,
+                })
+            } else {
+                by_slug.insert(&episode.slug, episode_ref.clone());
+                resolve_id(
+                    episode_ref,
+                    persisted.get(&episode.slug).copied(),
+                    &reserved,
+                    &mut by_position,
+                    &mut by_id,
+                )
+            };
+
+            if let Ok(id) = resolved {
+                assigned.insert(&episode.slug, id);
+            }
+            assignment.insert((season.id, idx), resolved);
+        }
+    }
+
+    // slugs that vanished from seasons.json give their id up to whichever new episode now claims it
+    for (slug, id) in persisted.iter() {
+        if by_id.contains_key(id) && !assigned.contains_key(slug.as_str()) {
+            ids_db.remove(slug.as_str())?;
+        }
+    }
+
+    for (slug, id) in assigned.iter() {
+        if persisted.get(*slug) != Some(id) {
+            ids_db.insert(*slug, id)?;
+        }
+    }
+
+    Ok(assignment)
+}
+
+/// Claims a position and an id for one episode: `persisted` if it has one, otherwise the first id from its
+/// candidate on that's neither `reserved` nor taken.
+// returns what ends up in an IdAssignment, which is only ever built once per update
+#[allow(clippy::result_large_err)]
+fn resolve_id(
+    episode: EpisodeRef,
+    persisted: Option<u64>,
+    reserved: &HashSet<u64>,
+    by_position: &mut HashMap<u64, EpisodeRef>,
+    by_id: &mut HashMap<u64, EpisodeRef>,
+) -> Result<u64, IdClash> {
+    let Some(position) = position(episode.season, episode.sorting_number) else {
+        return Err(IdClash::Overflow(episode));
+    };
+
+    if let Some(first) = by_position.get(&position) {
+        return Err(IdClash::DuplicateId {
+            id: position,
+            first: first.clone(),
+            second: episode,
+        });
+    }
+
+    let id = match persisted {
+        Some(id) => id,
+        None => {
+            let season = position >> 32;
+            let free = (position..)
+                .take_while(|id| id >> 32 == season)
+                .find(|id| !reserved.contains(id) && !by_id.contains_key(id));
+            match free {
+                Some(id) => id,
+                None => return Err(IdClash::Overflow(episode)),
+            }
+        }
+    };
+
+    if let Some(first) = by_id.get(&id) {
+        return Err(IdClash::DuplicateId {
+            id,
+            first: first.clone(),
+            second: episode,
+        });
+    }
+
+    by_position.insert(position, episode.clone());
+    by_id.insert(id, episode);
+    Ok(id)
+}
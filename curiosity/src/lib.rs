@@ -2,14 +2,19 @@ use std::path::PathBuf;
 
 pub mod db;
 pub mod docs_accessor;
+pub mod episode_ids;
+pub mod filters;
+pub mod report;
 pub mod schema;
 pub mod sentence;
 pub mod serialization_crimes;
+pub mod snapshot;
+pub mod stop_words;
 pub mod store;
+pub mod transcript;
 
-#[cfg(feature = "actix")]
-use actix_web::{body::BoxBody, http::StatusCode, HttpResponseBuilder, ResponseError};
 use sentence::*;
+use transcript::TranscriptFormat;
 
 use strum::{AsRefStr, Display, EnumString, FromRepr, IntoStaticStr};
 
@@ -33,8 +38,10 @@ use thiserror::Error;
     serde::Deserialize,
     Ord,
     PartialOrd,
+    Hash,
 )]
 #[serde(rename_all = "kebab-case")]
+#[archive(check_bytes)]
 #[archive_attr(derive(
     Debug,
     PartialEq,
@@ -108,6 +115,7 @@ pub enum SeasonId {
     PartialOrd,
     Hash,
 )]
+#[archive(check_bytes)]
 #[archive_attr(derive(
     PartialEq,
     Eq,
@@ -127,6 +135,7 @@ pub enum SeasonId {
     PartialOrd,
 ))]
 #[archive_attr(serde(rename_all = "kebab-case"))]
+#[archive_attr(strum(serialize_all = "kebab-case"))]
 #[serde(rename_all = "kebab-case")]
 #[repr(u8)]
 pub enum Friend {
@@ -185,9 +194,20 @@ pub struct Episode {
 #[derive(serde::Deserialize)]
 pub struct DownloadOptions {
     pub plain: PathBuf,
+    /// Overrides the format detected from `plain`'s extension and contents.
+    #[serde(default)]
+    pub format: Option<TranscriptFormat>,
+}
+
+impl DownloadOptions {
+    pub fn format(&self, text: &str) -> TranscriptFormat {
+        self.format
+            .unwrap_or_else(|| TranscriptFormat::detect(&self.plain, text))
+    }
 }
 
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Debug, Clone)]
+#[archive(check_bytes)]
 #[archive_attr(derive(Debug))]
 pub struct StoredEpisode {
     pub id: u64,
@@ -214,8 +234,17 @@ pub enum CuriosityError {
     REDBError(#[from] redb::Error),
     #[error(transparent)]
     PostcardError(#[from] postcard::Error),
+    #[error(transparent)]
+    ZipError(#[from] zip::result::ZipError),
+    #[error("invalid snapshot: {0}")]
+    InvalidSnapshot(String),
     #[error("not found")]
     NotFound,
+    #[error("stored episode {0} is invalid: {1}")]
+    InvalidDocument(u64, String),
+    /// Returned by an `add_documents` reader to abandon the rebuild; nothing it did is committed.
+    #[error("cancelled")]
+    Cancelled,
 }
 
 pub type CuriosityResult<T> = Result<T, CuriosityError>;
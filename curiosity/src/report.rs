@@ -0,0 +1,82 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{Episode, SeasonId};
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum IngestionOutcome {
+    Skipped,
+    Failed,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct IngestionEntry {
+    pub season: SeasonId,
+    pub slug: String,
+    pub title: String,
+    pub outcome: IngestionOutcome,
+    pub reason: String,
+}
+
+/// Summary of one `add_documents` run: how many episodes made it in, and why the rest didn't.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+pub struct IngestionReport {
+    /// unix timestamps, in milliseconds
+    pub started_at: u64,
+    pub finished_at: u64,
+    pub indexed: usize,
+    pub entries: Vec<IngestionEntry>,
+}
+
+impl IngestionReport {
+    pub fn start() -> IngestionReport {
+        IngestionReport {
+            started_at: unix_millis(),
+            ..Default::default()
+        }
+    }
+
+    pub fn finish(&mut self) {
+        self.finished_at = unix_millis();
+    }
+
+    pub fn skip(&mut self, season: SeasonId, episode: &Episode, reason: impl ToString) {
+        let reason = reason.to_string();
+        tracing::debug!(season = %season, slug = %episode.slug, reason, "skipped episode");
+        self.push(season, episode, IngestionOutcome::Skipped, reason);
+    }
+
+    pub fn fail(&mut self, season: SeasonId, episode: &Episode, reason: impl ToString) {
+        let reason = reason.to_string();
+        tracing::warn!(season = %season, slug = %episode.slug, reason, "failed to ingest episode");
+        self.push(season, episode, IngestionOutcome::Failed, reason);
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &IngestionEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.outcome == IngestionOutcome::Failed)
+    }
+
+    fn push(
+        &mut self,
+        season: SeasonId,
+        episode: &Episode,
+        outcome: IngestionOutcome,
+        reason: impl ToString,
+    ) {
+        self.entries.push(IngestionEntry {
+            season,
+            slug: episode.slug.clone(),
+            title: episode.title.clone(),
+            outcome,
+            reason: reason.to_string(),
+        });
+    }
+}
+
+pub fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
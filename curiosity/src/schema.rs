@@ -3,8 +3,13 @@ use tantivy::schema::*;
 pub fn build_schema() -> Schema {
     let mut schema_builder = Schema::builder();
 
-    schema_builder.add_u64_field("episode_id", STORED | FAST);
+    schema_builder.add_u64_field("episode_id", STORED | INDEXED | FAST);
     schema_builder.add_u64_field("season", INDEXED | FAST);
+    schema_builder.add_u64_field("sorting_number", INDEXED);
+    // see episode_ids::position, results are ordered by it
+    schema_builder.add_u64_field("position", INDEXED | FAST);
+    // one value per distinct speaker with a line in the episode
+    schema_builder.add_u64_field("speakers", INDEXED);
     schema_builder.add_text_field("title", TEXT);
     schema_builder.add_text_field("body", TEXT);
     schema_builder.build()
@@ -1,14 +1,15 @@
 use core::slice;
-use std::{collections::HashMap, ops::Range, str::FromStr};
-
-use line_span::LineSpans;
+use std::{collections::HashMap, ops::Range};
 
 use memchr::memmem::Finder;
 use nyoom_json::{ArrayWriter, JsonBuffer, UnescapedStr};
 use rkyv::Archive;
 use serde::ser::SerializeStruct;
 
-use crate::{CuriosityResult, Friend};
+use crate::{
+    transcript::{Timestamp, Turn},
+    CuriosityResult, Friend,
+};
 
 use smallvec::SmallVec;
 use tantivy::tokenizer::TextAnalyzer;
@@ -35,18 +36,20 @@ impl From<CopyableRange> for Range<usize> {
 }
 
 #[derive(Archive, rkyv::Serialize, rkyv::Deserialize, Debug, Clone)]
-#[archive(archived = "ArchivedSentence")]
+#[archive(archived = "ArchivedSentence", check_bytes)]
 #[archive_attr(derive(Debug))]
 pub struct Sentence {
-    #[archive(skip)]
     pub author: Friend,
     pub start_in_original: usize,
     pub len: usize,
+    pub start: Option<Timestamp>,
+    pub end: Option<Timestamp>,
     pub tokens_by_position: Vec<SmallToken>,
     pub terms_by_position: Vec<u32>,
 }
 
 #[derive(Archive, rkyv::Serialize, rkyv::Deserialize, Debug, Clone)]
+#[archive(check_bytes)]
 #[archive_attr(derive(Debug))]
 pub struct SmallToken {
     pub start: usize,
@@ -111,12 +114,24 @@ impl Sentence {
 
     pub fn tokenize(
         text: &str,
+        turns: &[Turn],
         tokenizer: &TextAnalyzer,
         term_map: &mut HashMap<String, u32>,
     ) -> CuriosityResult<Vec<Sentence>> {
-        text.line_spans()
-            .map(|line| -> CuriosityResult<Sentence> {
-                let (sentence_start, sentence) = (line.start(), line.as_str());
+        turns
+            .iter()
+            .flat_map(|turn| {
+                sentence_spans(&text[turn.span.clone()])
+                    .into_iter()
+                    .map(move |span| {
+                        (
+                            turn,
+                            turn.span.start + span.start..turn.span.start + span.end,
+                        )
+                    })
+            })
+            .map(|(turn, span)| -> CuriosityResult<Sentence> {
+                let (sentence_start, sentence) = (span.start, &text[span]);
                 let mut tokens = Vec::new();
                 let mut stream = tokenizer.token_stream(sentence);
 
@@ -136,25 +151,16 @@ impl Sentence {
                     })
                 }
 
-                let author = Friend::from_str(
-                    &sentence
-                        .split_once(':')
-                        .map_or("Unknown", |v| v.0)
-                        .split_whitespace()
-                        .next()
-                        .unwrap_or("Unknown")
-                        .to_lowercase(),
-                )
-                .unwrap_or(Friend::Unknown);
-
                 tokens.sort_by_key(|v| v.start);
 
                 let terms_by_position = tokens.iter().map(|v| v.term).collect::<Vec<_>>();
 
                 Ok(Sentence {
-                    author,
+                    author: turn.speaker,
                     start_in_original: sentence_start,
                     len: sentence.len(),
+                    start: turn.start,
+                    end: turn.end,
                     tokens_by_position: tokens,
                     terms_by_position,
                 })
@@ -163,7 +169,97 @@ impl Sentence {
     }
 }
 
+// a full stop after these doesn't end a sentence
+const ABBREVIATIONS: &[&str] = &["mr", "mrs", "ms", "dr", "st", "vs", "etc", "e.g", "i.e"];
+
+/// Splits a speaker turn into sentences and paragraphs, returning ranges relative to `turn`.
+/// A sentence ends at a line break, or at `.`, `!`, `?` or `…` followed by whitespace and something that looks like the start of a new one.
+pub fn sentence_spans(turn: &str) -> SmallVec<[Range<usize>; 4]> {
+    let mut spans = SmallVec::new();
+    let mut push_span = |start: usize, end: usize| {
+        let sentence = &turn[start..end];
+        let trimmed = sentence.trim_start();
+        let start = start + (sentence.len() - trimmed.len());
+        let trimmed = trimmed.trim_end();
+        if !trimmed.is_empty() {
+            spans.push(start..start + trimmed.len());
+        }
+    };
+
+    let mut sentence_start = 0;
+    let mut chars = turn.char_indices().peekable();
+
+    while let Some((idx, c)) = chars.next() {
+        let sentence_end = match c {
+            '\n' => idx,
+            '.' | '!' | '?' | '…' => {
+                // closing quotes, brackets and repeated punctuation stay with the sentence they close
+                let mut end = idx + c.len_utf8();
+                while let Some(&(next_idx, next)) = chars.peek() {
+                    if !matches!(
+                        next,
+                        '.' | '!' | '?' | '…' | '"' | '\'' | ')' | ']' | '”' | '’'
+                    ) {
+                        break;
+                    }
+                    end = next_idx + next.len_utf8();
+                    chars.next();
+                }
+
+                let word = turn[sentence_start..idx]
+                    .rsplit(char::is_whitespace)
+                    .next()
+                    .unwrap_or_default()
+                    .to_lowercase();
+                if c == '.' && ABBREVIATIONS.contains(&word.as_str()) {
+                    continue;
+                }
+
+                let rest = &turn[end..];
+                let next = rest.trim_start().chars().next();
+                match next {
+                    Some(next)
+                        if rest.starts_with(char::is_whitespace)
+                            && (next.is_uppercase()
+                                || next.is_numeric()
+                                || matches!(next, '"' | '“' | '[' | '(')) =>
+                    {
+                        end
+                    }
+                    _ => continue,
+                }
+            }
+            _ => continue,
+        };
+
+        push_span(sentence_start, sentence_end);
+        sentence_start = sentence_end;
+    }
+
+    push_span(sentence_start, turn.len());
+    spans
+}
+
 impl ArchivedSentence {
+    pub fn get<'a>(&self, body: &'a str) -> &'a str {
+        let start = self.start_in_original.value() as usize;
+        &body[start..start + self.len.value() as usize]
+    }
+
+    /// 1-based line of the transcript the sentence starts on.
+    pub fn line_number(&self, body: &str) -> usize {
+        let start = self.start_in_original.value() as usize;
+        memchr::memchr_iter(b'\n', &body.as_bytes()[..start]).count() + 1
+    }
+
+    /// Start and end of the sentence in the episode's audio, in milliseconds.
+    pub fn timestamps(&self) -> (Option<u32>, Option<u32>) {
+        (
+            self.start.as_ref().map(|t| t.millis.value()),
+            self.end.as_ref().map(|t| t.millis.value()),
+        )
+    }
+
     pub fn highlight<'b>(
         &self,
         terms: &[u32],
@@ -309,9 +405,91 @@ pub fn collapse_overlapped_ranges(ranges: &[CopyableRange]) -> SmallVec<[Copyabl
     result
 }
 
+/// The first `len` bytes of `text`, cut back to the last word boundary.
+fn head(text: &str, len: usize) -> &str {
+    let mut end = len.min(text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    let head = &text[..end];
+    if end == text.len() {
+        return head;
+    }
+
+    // whitespace isn't always one byte long, e.g. a non-breaking space
+    match head.char_indices().rev().find(|(_, c)| c.is_whitespace()) {
+        Some((space, c)) => &head[..space + c.len_utf8()],
+        None => head,
+    }
+}
+
+/// The last `len` bytes of `text`, moved forward to the next word boundary.
+fn tail(text: &str, len: usize) -> &str {
+    let mut start = text.len().saturating_sub(len);
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+
+    let tail = &text[start..];
+    match tail.find(char::is_whitespace) {
+        Some(space) if start > 0 => &tail[space..],
+        _ => tail,
+    }
+}
+
 pub struct HighlightedSentence<'a>(SmallVec<[SentencePart<'a>; 8]>);
 
 impl<'a> HighlightedSentence<'a> {
+    pub fn parts(&self) -> &[SentencePart<'a>] {
+        &self.0
+    }
+
+    /// Shortens the unhighlighted text around and between matches so the snippet stays near `max_len` bytes,
+    /// marking whatever was cut with an ellipsis. Highlighted parts are never cut.
+    pub fn trim(&mut self, max_len: usize) {
+        let total_len: usize = self.0.iter().map(|part| part.text().len()).sum();
+        if total_len <= max_len {
+            return;
+        }
+
+        let highlighted_len: usize = self
+            .0
+            .iter()
+            .filter(|part| part.is_highlighted())
+            .map(|part| part.text().len())
+            .sum();
+        let normal_count = self.0.iter().filter(|part| !part.is_highlighted()).count();
+        let budget = max_len.saturating_sub(highlighted_len) / normal_count.max(1);
+
+        let last_idx = self.0.len() - 1;
+        let mut trimmed: SmallVec<[SentencePart<'a>; 8]> = SmallVec::new();
+
+        for (idx, part) in self.0.iter().enumerate() {
+            let text = match part {
+                SentencePart::Normal(text) if text.len() > budget => *text,
+                part => {
+                    trimmed.push(part.clone());
+                    continue;
+                }
+            };
+
+            if idx == 0 {
+                trimmed.push(SentencePart::Normal("…"));
+                trimmed.push(SentencePart::Normal(tail(text, budget)));
+            } else if idx == last_idx {
+                trimmed.push(SentencePart::Normal(head(text, budget)));
+                trimmed.push(SentencePart::Normal("…"));
+            } else {
+                trimmed.push(SentencePart::Normal(head(text, budget / 2)));
+                trimmed.push(SentencePart::Normal("…"));
+                trimmed.push(SentencePart::Normal(tail(text, budget / 2)));
+            }
+        }
+
+        self.0 = trimmed;
+    }
+
     pub fn serialize_into<S: JsonBuffer>(&self, mut ser: ArrayWriter<S>) {
         for part in &self.0 {
             let mut part_writer = ser.add_object();
@@ -329,3 +507,97 @@ impl<'a> HighlightedSentence<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spans(turn: &str) -> Vec<&str> {
+        sentence_spans(turn)
+            .into_iter()
+            .map(|span| &turn[span])
+            .collect()
+    }
+
+    fn trimmed(parts: &[SentencePart<'_>], max_len: usize) -> String {
+        let mut sentence = HighlightedSentence(parts.iter().cloned().collect());
+        sentence.trim(max_len);
+        SentencePart::display_string(sentence.parts())
+    }
+
+    #[test]
+    fn head_cuts_at_multibyte_whitespace() {
+        assert_eq!(head("abcd\u{a0}efgh ijkl", 7), "abcd\u{a0}");
+        assert_eq!(head("abcd\u{3000}efgh", 8), "abcd\u{3000}");
+        assert_eq!(head("abcd efgh", 6), "abcd ");
+        assert_eq!(head("short", 10), "short");
+        assert_eq!(head("nospaces", 4), "nosp");
+    }
+
+    #[test]
+    fn head_never_splits_a_char() {
+        assert_eq!(head("ééé", 3), "é");
+        assert_eq!(head("…and", 2), "");
+    }
+
+    #[test]
+    fn tail_cuts_at_multibyte_whitespace() {
+        assert_eq!(tail("abcd efgh\u{a0}ijkl", 7), "\u{a0}ijkl");
+        assert_eq!(tail("abcd efgh", 6), " efgh");
+        assert_eq!(tail("short", 10), "short");
+        assert_eq!(tail("ééé", 3), "é");
+    }
+
+    #[test]
+    fn trim_keeps_highlights_and_marks_cuts() {
+        let parts = [
+            SentencePart::Normal("Austin: once upon a time there was a "),
+            SentencePart::Highlighted("dragon"),
+            SentencePart::Normal(" who lived under a very large mountain."),
+        ];
+
+        assert_eq!(trimmed(&parts, 30), "… was a *dragon* who lived …");
+        assert_eq!(trimmed(&parts, 500), SentencePart::display_string(&parts));
+    }
+
+    #[test]
+    fn trim_handles_multibyte_whitespace_and_punctuation() {
+        let parts = [
+            SentencePart::Normal("Jack:\u{a0}well\u{a0}…\u{a0}the\u{a0}"),
+            SentencePart::Highlighted("dragon"),
+            SentencePart::Normal("\u{a0}said\u{a0}“no”\u{a0}—\u{a0}twice\u{a0}over"),
+        ];
+
+        for max_len in 0..60 {
+            let snippet = trimmed(&parts, max_len);
+            assert!(snippet.contains("*dragon*"), "{max_len}: {snippet}");
+        }
+    }
+
+    #[test]
+    fn sentence_spans_split_on_punctuation() {
+        assert_eq!(
+            spans("Hello there. How are you? Fine!"),
+            ["Hello there.", "How are you?", "Fine!"]
+        );
+        assert_eq!(
+            spans("Mr. Smith went home. He slept."),
+            ["Mr. Smith went home.", "He slept."]
+        );
+        assert_eq!(spans("one\n\ntwo"), ["one", "two"]);
+        assert_eq!(spans("lowercase. keeps going"), ["lowercase. keeps going"]);
+    }
+
+    #[test]
+    fn sentence_spans_handle_multibyte_punctuation_and_whitespace() {
+        assert_eq!(
+            spans("Wait…\u{a0}What? “Really.” Yes."),
+            ["Wait…", "What?", "“Really.”", "Yes."]
+        );
+        assert_eq!(
+            spans("He said “stop.” Then left."),
+            ["He said “stop.”", "Then left."]
+        );
+        assert_eq!(spans("\u{3000}Ünïcödé. Ök.\u{3000}"), ["Ünïcödé.", "Ök."]);
+    }
+}
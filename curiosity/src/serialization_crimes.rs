@@ -8,6 +8,10 @@ pub fn page_size_default() -> usize {
     50
 }
 
+pub fn snippet_length_default() -> usize {
+    300
+}
+
 // i love that serde makes me write these. i love it actually
 pub fn is_false(b: &bool) -> bool {
     *b
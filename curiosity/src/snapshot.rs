@@ -0,0 +1,287 @@
+use std::{
+    fs::File,
+    io::{self, Read, Seek, Write},
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+use tantivy::{directory::MmapDirectory, Index};
+use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::{
+    report::unix_millis, schema::build_schema, store::Store, term_map::TermMap, CuriosityError,
+    CuriosityResult,
+};
+
+/// Version of the snapshot archive layout. Bump it whenever the manifest or the files in it change.
+pub const SNAPSHOT_FORMAT: u32 = 1;
+
+const MANIFEST_PATH: &str = "manifest.json";
+
+/// Files no snapshot can do without. The index's segments are listed in its `meta.json`, and opening it checks they're there.
+const REQUIRED_FILES: [&str; 3] = ["store.redb", "terms.postcard", "index/meta.json"];
+
+/// Describes a snapshot archive: which format it's in, what it was taken from, and a hash of every file in it.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct SnapshotManifest {
+    pub format: u32,
+    /// unix timestamp, in milliseconds
+    pub created_at: u64,
+    pub generation: u64,
+    pub files: Vec<SnapshotFile>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct SnapshotFile {
+    /// relative to the data folder, `/`-separated
+    pub path: String,
+    pub size: u64,
+    /// hex-encoded
+    pub sha256: String,
+}
+
+/// Zips up the store, term map and index files in `folder`. Callers make sure nothing writes to them meanwhile.
+pub(crate) fn write_snapshot<W: Write + Seek>(
+    folder: &Path,
+    generation: u64,
+    out: W,
+) -> CuriosityResult<SnapshotManifest> {
+    let mut zip = ZipWriter::new(out);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let mut files = Vec::new();
+    for path in data_files(folder)? {
+        let mut reader = HashingReader::new(File::open(folder.join(&path))?);
+        zip.start_file(&path, options)?;
+        let size = io::copy(&mut reader, &mut zip)?;
+
+        files.push(SnapshotFile {
+            path,
+            size,
+            sha256: reader.hex_digest(),
+        });
+    }
+
+    let manifest = SnapshotManifest {
+        format: SNAPSHOT_FORMAT,
+        created_at: unix_millis(),
+        generation,
+        files,
+    };
+
+    zip.start_file(MANIFEST_PATH, options)?;
+    serde_json::to_writer_pretty(&mut zip, &manifest)
+        .map_err(|e| CuriosityError::InvalidSnapshot(e.to_string()))?;
+    zip.finish()?;
+
+    Ok(manifest)
+}
+
+/// Writes a snapshot archive of the data in `folder` to `out` without opening it as a [`Db`](crate::db::Db),
+/// which would discard anything in an outdated format. Nothing else may have `folder` open meanwhile.
+pub fn backup<W: Write + Seek>(
+    folder: impl AsRef<Path>,
+    out: W,
+) -> CuriosityResult<SnapshotManifest> {
+    let folder = folder.as_ref();
+    let store_path = folder.join("store.redb");
+    if !store_path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no store at {}", store_path.display()),
+        )
+        .into());
+    }
+
+    let store = Store::open(store_path)?;
+    let outdated = store.outdated_formats()?;
+    if !outdated.is_empty() {
+        return Err(CuriosityError::InvalidSnapshot(format!(
+            "refusing to back up data in an outdated format ({})",
+            outdated.join(", ")
+        )));
+    }
+
+    let generation = store.generation()?;
+    drop(store);
+
+    write_snapshot(folder, generation, out)
+}
+
+/// Restores a snapshot archive into `folder`, which must not be open.
+///
+/// The archive is unpacked next to `folder` and checked against its manifest, and only then swapped in.
+/// Whatever was in `folder` before is moved to `<folder>.old`, replacing any previous one.
+pub fn restore(
+    archive: impl Read + Seek,
+    folder: impl AsRef<Path>,
+) -> CuriosityResult<SnapshotManifest> {
+    let folder = folder.as_ref();
+    let mut archive = ZipArchive::new(archive)?;
+
+    let manifest: SnapshotManifest = serde_json::from_reader(archive.by_name(MANIFEST_PATH)?)
+        .map_err(|e| CuriosityError::InvalidSnapshot(format!("bad manifest: {e}")))?;
+    if manifest.format != SNAPSHOT_FORMAT {
+        return Err(CuriosityError::InvalidSnapshot(format!(
+            "snapshot format {} isn't supported, expected {SNAPSHOT_FORMAT}",
+            manifest.format
+        )));
+    }
+
+    for required in REQUIRED_FILES {
+        if !manifest.files.iter().any(|file| file.path == required) {
+            return Err(CuriosityError::InvalidSnapshot(format!(
+                "{required} is missing from the manifest"
+            )));
+        }
+    }
+
+    let staging = sibling(folder, "restoring");
+    if staging.exists() {
+        std::fs::remove_dir_all(&staging)?;
+    }
+
+    if let Err(e) = unpack(&mut archive, &manifest, &staging) {
+        let _ = std::fs::remove_dir_all(&staging);
+        return Err(e);
+    }
+
+    if folder.exists() {
+        let old = sibling(folder, "old");
+        if old.exists() {
+            std::fs::remove_dir_all(&old)?;
+        }
+        std::fs::rename(folder, &old)?;
+    }
+    std::fs::rename(&staging, folder)?;
+
+    Ok(manifest)
+}
+
+/// Extracts the files listed in `manifest` into `folder`, checking each against its hash,
+/// then makes sure they open, belong together and are in the current format.
+fn unpack<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    manifest: &SnapshotManifest,
+    folder: &Path,
+) -> CuriosityResult<()> {
+    for file in &manifest.files {
+        let relative = Path::new(&file.path);
+        if relative.is_absolute() || relative.components().any(|c| c.as_os_str() == "..") {
+            return Err(CuriosityError::InvalidSnapshot(format!(
+                "{} points outside the data folder",
+                file.path
+            )));
+        }
+
+        let target = folder.join(relative);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut reader = HashingReader::new(archive.by_name(&file.path)?);
+        let size = io::copy(&mut reader, &mut File::create(&target)?)?;
+        if size != file.size || reader.hex_digest() != file.sha256 {
+            return Err(CuriosityError::InvalidSnapshot(format!(
+                "{} doesn't match the manifest",
+                file.path
+            )));
+        }
+    }
+
+    // Db::new would quietly discard documents in an outdated format, so those are rejected here
+    let store = Store::open(folder.join("store.redb"))?;
+    let outdated = store.outdated_formats()?;
+    if !outdated.is_empty() {
+        return Err(CuriosityError::InvalidSnapshot(format!(
+            "written in an outdated format ({})",
+            outdated.join(", ")
+        )));
+    }
+
+    let generation = store.generation()?;
+    if generation != manifest.generation {
+        return Err(CuriosityError::InvalidSnapshot(format!(
+            "store is at generation {generation}, the manifest says {}",
+            manifest.generation
+        )));
+    }
+    drop(store);
+
+    // opened directly rather than through Db::new, which would throw away an index with a different schema
+    let index = Index::open(MmapDirectory::open(folder.join("index"))?)?;
+    if index.schema() != build_schema() {
+        return Err(CuriosityError::InvalidSnapshot(
+            "index was built with a different schema".to_owned(),
+        ));
+    }
+    index.reader()?;
+
+    postcard::from_bytes::<TermMap>(&std::fs::read(folder.join("terms.postcard"))?)
+        .map_err(|e| CuriosityError::InvalidSnapshot(format!("bad term map: {e}")))?;
+
+    Ok(())
+}
+
+/// Everything a [`Db`](crate::db::Db) keeps on disk, relative to its folder. Tantivy's lock files are left out.
+fn data_files(folder: &Path) -> CuriosityResult<Vec<String>> {
+    for required in REQUIRED_FILES {
+        if !folder.join(required).exists() {
+            return Err(CuriosityError::InvalidSnapshot(format!(
+                "nothing to back up, there's no {required} yet"
+            )));
+        }
+    }
+
+    let mut files = vec!["store.redb".to_owned(), "terms.postcard".to_owned()];
+
+    let mut index_files = Vec::new();
+    for entry in std::fs::read_dir(folder.join("index"))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if entry.metadata()?.is_file() && !name.ends_with(".lock") {
+            index_files.push(format!("index/{name}"));
+        }
+    }
+    index_files.sort();
+    files.extend(index_files);
+
+    Ok(files)
+}
+
+fn sibling(folder: &Path, suffix: &str) -> PathBuf {
+    let mut name = folder.file_name().unwrap_or_default().to_owned();
+    name.push(".");
+    name.push(suffix);
+    folder.with_file_name(name)
+}
+
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> HashingReader<R> {
+    fn new(inner: R) -> Self {
+        HashingReader {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    fn hex_digest(self) -> String {
+        self.hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
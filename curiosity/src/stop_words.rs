@@ -0,0 +1,40 @@
+use std::{collections::HashSet, path::Path};
+
+use crate::CuriosityResult;
+
+/// Words too common to be worth highlighting on their own, like "the" or "of".
+#[derive(Default, Clone, Debug)]
+pub struct StopWords {
+    words: HashSet<String>,
+}
+
+impl StopWords {
+    /// Reads one word per line. Blank lines and lines starting with `#` are skipped.
+    pub fn from_file(path: impl AsRef<Path>) -> CuriosityResult<StopWords> {
+        Ok(StopWords::from_list(&std::fs::read_to_string(path)?))
+    }
+
+    pub fn from_list(list: &str) -> StopWords {
+        let words = list
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            // the body tokenizer lowercases everything, so tokens are compared lowercased too
+            .map(str::to_lowercase)
+            .collect();
+
+        StopWords { words }
+    }
+
+    pub fn contains(&self, token: &str) -> bool {
+        self.words.contains(token)
+    }
+
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+}
@@ -1,11 +1,16 @@
-use std::{borrow::Cow, ops::Deref, sync::Arc};
+use std::{borrow::Cow, collections::HashSet, ops::Deref, path::Path, sync::Arc};
 
-use redb::{MultimapTableDefinition, RedbKey, RedbValue, TableDefinition, WriteTransaction};
+use parking_lot::Mutex;
+use redb::{
+    MultimapTableDefinition, ReadableTable, RedbKey, RedbValue, TableDefinition, WriteTransaction,
+};
 use rend::u32_le;
 use yoke::{Yoke, Yokeable};
-use zerocopy::AsBytes;
+use zerocopy::{AsBytes, LayoutVerified};
 
-use crate::{docs_accessor::SimpleDocsAccessor, CuriosityError, CuriosityResult};
+use crate::{
+    docs_accessor::SimpleDocsAccessor, report::IngestionReport, CuriosityError, CuriosityResult,
+};
 
 #[derive(Yokeable)]
 #[repr(transparent)]
@@ -66,7 +71,7 @@ impl ReadTransaction {
 
 /* SPECIFICS */
 
-#[derive(zerocopy::AsBytes, zerocopy::FromBytes, Debug)]
+#[derive(zerocopy::AsBytes, zerocopy::FromBytes, zerocopy::Unaligned, Debug)]
 #[repr(C)]
 pub struct TermsToSentencesId {
     doc: [u8; 8],
@@ -84,6 +89,14 @@ impl TermsToSentencesId {
     pub fn set_term(&mut self, term: u32) {
         self.term = term.to_le_bytes();
     }
+
+    pub fn doc(&self) -> u64 {
+        u64::from_le_bytes(self.doc)
+    }
+
+    pub fn term(&self) -> u32 {
+        u32::from_le_bytes(self.term)
+    }
 }
 
 impl RedbValue for TermsToSentencesId {
@@ -101,8 +114,16 @@ impl RedbValue for TermsToSentencesId {
     where
         Self: 'a,
     {
-        debug_assert!(data.len() == std::mem::size_of::<TermsToSentencesId>());
-        unsafe { &*(data.as_ptr() as *const TermsToSentencesId) }
+        // only made of byte arrays, so any address will do; the length is what needs checking
+        LayoutVerified::<_, TermsToSentencesId>::new_unaligned(data)
+            .unwrap_or_else(|| {
+                panic!(
+                    "TermsToSentencesId is {} bytes, got {}",
+                    std::mem::size_of::<TermsToSentencesId>(),
+                    data.len()
+                )
+            })
+            .into_ref()
     }
 
     fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
@@ -154,13 +175,34 @@ impl RedbValue for SentenceList<'_> {
         None
     }
 
+    /// Borrows `data` if it's aligned for `u32_le`, and copies it otherwise: redb makes no promises about alignment.
     fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
     where
         Self: 'a,
     {
-        let (_, data, _) = unsafe { data.align_to::<u32_le>() };
-        SentenceList {
-            ids: Cow::Borrowed(data),
+        let ids = data.chunks_exact(std::mem::size_of::<u32_le>());
+        assert!(
+            ids.remainder().is_empty(),
+            "SentenceList of {} bytes isn't a whole number of ids",
+            data.len()
+        );
+
+        if data.as_ptr().align_offset(std::mem::align_of::<u32_le>()) == 0 {
+            // safe: aligned, sized to a whole number of ids, and any bit pattern is a valid u32_le
+            let ids = unsafe {
+                std::slice::from_raw_parts(data.as_ptr() as *const u32_le, ids.len())
+            };
+
+            SentenceList {
+                ids: Cow::Borrowed(ids),
+            }
+        } else {
+            SentenceList {
+                ids: Cow::Owned(
+                    ids.map(|id| u32_le::new(u32::from_le_bytes(id.try_into().unwrap())))
+                        .collect(),
+                ),
+            }
         }
     }
 
@@ -169,8 +211,13 @@ impl RedbValue for SentenceList<'_> {
         Self: 'a,
         Self: 'b,
     {
-        let (_, data, _) = unsafe { value.ids.align_to::<u8>() };
-        data
+        // safe: u32_le has no padding, and u8 has no alignment requirement
+        unsafe {
+            std::slice::from_raw_parts(
+                value.ids.as_ptr() as *const u8,
+                std::mem::size_of_val(value.ids.as_ref()),
+            )
+        }
     }
 
     fn type_name() -> redb::TypeName {
@@ -183,9 +230,86 @@ pub struct Store {
     pub db: Arc<redb::Database>,
     pub docs: TableDefinition<'static, u64, &'static [u8]>,
     pub terms_to_sentences: TableDefinition<'static, TermsToSentencesId, SentenceList<'static>>,
+    pub episode_ids: TableDefinition<'static, &'static str, u64>,
+    pub reports: TableDefinition<'static, u64, &'static [u8]>,
+    pub meta: TableDefinition<'static, &'static str, u64>,
+    pub sources: TableDefinition<'static, &'static str, &'static [u8]>,
+    /// The server's update runs by id, kept like `reports` so the history outlives restarts.
+    pub update_runs: TableDefinition<'static, u64, &'static [u8]>,
+    /// Documents that passed validation, so each one is only checked on its first read.
+    validated: Arc<Mutex<ValidatedDocs>>,
+}
+
+/// Ids of the documents validated since the store got to `generation`. Rebuilding the documents bumps the
+/// generation in the same transaction, so a set from an older generation says nothing about the current documents.
+#[derive(Default)]
+pub(crate) struct ValidatedDocs {
+    generation: u64,
+    ids: HashSet<u64>,
+}
+
+impl ValidatedDocs {
+    pub(crate) fn contains(&self, generation: u64, id: u64) -> bool {
+        self.generation == generation && self.ids.contains(&id)
+    }
+
+    pub(crate) fn insert(&mut self, generation: u64, id: u64) {
+        if generation > self.generation {
+            self.generation = generation;
+            self.ids.clear();
+        }
+
+        // a transaction that started before the latest rebuild validated a document that's gone by now
+        if generation == self.generation {
+            self.ids.insert(id);
+        }
+    }
+}
+
+/// Key in [`Store::meta`] of the counter bumped every time the index is rebuilt.
+pub const GENERATION_KEY: &str = "generation";
+
+/// Versions of the layouts documents are written to disk in, recorded in [`Store::meta`] by every rebuild.
+/// Bump one whenever what it describes changes shape: data written in another version is thrown away
+/// when the [`crate::db::Db`] is opened, and rebuilt by the next update.
+pub const FORMAT_VERSIONS: &[(&str, u64)] = &[
+    // rkyv layout of StoredEpisode, in `docs`
+    ("format.stored_episode", 1),
+    // TermsToSentencesId keys and SentenceList values, in `terms_to_sentences`
+    ("format.terms_to_sentences", 1),
+    // postcard encoding of the TermMap, in terms.postcard
+    ("format.term_map", 1),
+];
+
+/// What the documents in the store were built from, so an update can tell whether anything changed.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SourceInfo {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub sha256: [u8; 32],
+    /// [`crate::transcript::PARSER_VERSION`] the documents were parsed with
+    pub parser_version: u32,
 }
 
 impl Store {
+    pub fn open(path: impl AsRef<Path>) -> CuriosityResult<Store> {
+        let store_env = redb::Database::builder()
+            .set_cache_size(1_000_000_000)
+            .create(path.as_ref())?;
+
+        Ok(Store {
+            db: Arc::new(store_env),
+            docs: TableDefinition::new("docs"),
+            terms_to_sentences: TableDefinition::new("terms_to_sentences"),
+            episode_ids: TableDefinition::new("episode_ids"),
+            reports: TableDefinition::new("reports"),
+            meta: TableDefinition::new("meta"),
+            sources: TableDefinition::new("sources"),
+            update_runs: TableDefinition::new("update_runs"),
+            validated: Arc::default(),
+        })
+    }
+
     pub fn begin_read(&self) -> CuriosityResult<ReadTransaction> {
         Ok(ReadTransaction {
             txn: Yoke::try_attach_to_cart(Arc::clone(&self.db), |db| {
@@ -195,16 +319,147 @@ impl Store {
         })
     }
 
-    pub fn begin_write(&self) -> CuriosityResult<WriteTransaction> {
+    pub fn begin_write(&self) -> CuriosityResult<WriteTransaction<'_>> {
         self.db.begin_write().map_err(CuriosityError::REDBError)
     }
 
+    pub fn episode_id(&self, slug: &str) -> CuriosityResult<Option<u64>> {
+        let txn = self.begin_read()?;
+        let ids = txn.open_table(self.episode_ids)?;
+        let id = ids.get(slug)?.map(|id| id.value());
+        Ok(id)
+    }
+
+    /// How many times the index has been rebuilt, so anything derived from search results can tell when it's outdated.
+    pub fn generation(&self) -> CuriosityResult<u64> {
+        let txn = self.begin_read()?;
+        let meta = match txn.open_table(self.meta) {
+            Ok(table) => table,
+            Err(CuriosityError::REDBError(redb::Error::TableDoesNotExist(_))) => return Ok(0),
+            Err(e) => return Err(e),
+        };
+
+        let generation = meta.get(GENERATION_KEY)?.map_or(0, |g| g.value());
+        Ok(generation)
+    }
+
+    /// Keys of the [`FORMAT_VERSIONS`] the stored documents weren't written in. Empty if there are no documents.
+    pub fn outdated_formats(&self) -> CuriosityResult<Vec<&'static str>> {
+        let txn = self.begin_read()?;
+        let has_docs = match txn.open_table(self.docs) {
+            Ok(docs) => docs.iter()?.next().is_some(),
+            Err(CuriosityError::REDBError(redb::Error::TableDoesNotExist(_))) => false,
+            Err(e) => return Err(e),
+        };
+
+        if !has_docs {
+            return Ok(Vec::new());
+        }
+
+        // documents from before versions were recorded have no meta table, or nothing in it
+        let meta = match txn.open_table(self.meta) {
+            Ok(meta) => Some(meta),
+            Err(CuriosityError::REDBError(redb::Error::TableDoesNotExist(_))) => None,
+            Err(e) => return Err(e),
+        };
+
+        let mut outdated = Vec::new();
+        for (key, version) in FORMAT_VERSIONS {
+            let stored = match meta.as_ref() {
+                Some(meta) => meta.get(*key)?.map(|v| v.value()),
+                None => None,
+            };
+
+            if stored != Some(*version) {
+                outdated.push(*key);
+            }
+        }
+
+        Ok(outdated)
+    }
+
+    /// Drops the documents and what's derived from them, keeping episode ids and reports. The generation is bumped,
+    /// so nothing issued for the old documents is trusted, and the source is forgotten, so the next update rebuilds.
+    pub fn discard_documents(&self) -> CuriosityResult<()> {
+        let txn = self.begin_write()?;
+        txn.delete_table(self.docs)?;
+        txn.delete_table(self.terms_to_sentences)?;
+        txn.delete_table(self.sources)?;
+
+        {
+            let mut meta = txn.open_table(self.meta)?;
+            let generation = meta.get(GENERATION_KEY)?.map_or(0, |g| g.value());
+            meta.insert(GENERATION_KEY, generation + 1)?;
+            for (key, _) in FORMAT_VERSIONS {
+                meta.remove(*key)?;
+            }
+        }
+
+        txn.commit()?;
+
+        Ok(())
+    }
+
+    /// What the documents were last built from, if they were built from `url`.
+    pub fn source(&self, url: &str) -> CuriosityResult<Option<SourceInfo>> {
+        let txn = self.begin_read()?;
+        let sources = match txn.open_table(self.sources) {
+            Ok(table) => table,
+            Err(CuriosityError::REDBError(redb::Error::TableDoesNotExist(_))) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let Some(source) = sources.get(url)? else {
+            return Ok(None);
+        };
+
+        // one recorded before the parser version was is as good as none, the next update rebuilds either way
+        Ok(postcard::from_bytes(source.value()).ok())
+    }
+
+    /// Records what the documents were built from. Only call it once they all were, since an update
+    /// skips a source it has seen before.
+    pub fn set_source(&self, url: &str, source: &SourceInfo) -> CuriosityResult<()> {
+        let txn = self.begin_write()?;
+        {
+            let mut sources = txn.open_table(self.sources)?;
+            sources.insert(url, postcard::to_stdvec(source)?.as_slice())?;
+        }
+        txn.commit()?;
+
+        Ok(())
+    }
+
+    pub fn latest_report(&self) -> CuriosityResult<Option<IngestionReport>> {
+        let txn = self.begin_read()?;
+        let reports = match txn.open_table(self.reports) {
+            Ok(table) => table,
+            Err(CuriosityError::REDBError(redb::Error::TableDoesNotExist(_))) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let Some(entry) = reports.iter()?.next_back() else {
+            return Ok(None);
+        };
+
+        let (_, report) = entry?;
+        Ok(Some(postcard::from_bytes(report.value())?))
+    }
+
     pub fn get_docs_accessor<'a>(
         &self,
         txn: &'a redb::ReadTransaction<'a>,
     ) -> CuriosityResult<SimpleDocsAccessor<'a>> {
+        let generation = match txn.open_table(self.meta) {
+            Ok(meta) => meta.get(GENERATION_KEY)?.map_or(0, |g| g.value()),
+            Err(redb::Error::TableDoesNotExist(_)) => 0,
+            Err(e) => return Err(e.into()),
+        };
+
         Ok(SimpleDocsAccessor {
             docs: txn.open_table(self.docs)?,
+            generation,
+            validated: Arc::clone(&self.validated),
         })
     }
 }
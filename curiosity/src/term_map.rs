@@ -82,7 +82,7 @@ impl TermMap {
         let mut out_vals = vec![0u32; values.len()];
 
         let hash_fn = FPHash::new(keys.clone());
-        for (key, val) in keys.into_iter().zip(values.into_iter()) {
+        for (key, val) in keys.into_iter().zip(values) {
             let idx = hash_fn.get(&key).unwrap() as usize;
             out_keys[idx] = key.into();
             out_vals[idx] = val;
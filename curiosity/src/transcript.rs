@@ -0,0 +1,329 @@
+use std::{ops::Range, path::Path, str::FromStr};
+
+use line_span::{LineSpan, LineSpans};
+
+use crate::Friend;
+
+/// Version of the parsers below. Bump it whenever one of them changes what gets indexed, so the next update
+/// rebuilds from the same archive instead of skipping it as unchanged.
+pub const PARSER_VERSION: u32 = 2;
+
+/// A point in an episode's audio, in milliseconds from the start.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize,
+)]
+#[archive(check_bytes)]
+#[archive_attr(derive(Debug, Clone, Copy))]
+pub struct Timestamp {
+    pub millis: u32,
+}
+
+impl Timestamp {
+    /// Parses `hh:mm:ss`, `mm:ss` and either of those with a `.fff` or `,fff` fraction (as in WebVTT and SRT).
+    pub fn parse(s: &str) -> Option<Timestamp> {
+        let s = s.trim();
+        let (clock, fraction) = match s.split_once(['.', ',']) {
+            Some((clock, fraction)) => (clock, Some(fraction)),
+            None => (s, None),
+        };
+
+        let mut seconds: u32 = 0;
+        let mut fields = 0;
+        for field in clock.split(':') {
+            if field.is_empty() || field.len() > 2 && fields > 0 {
+                return None;
+            }
+
+            seconds = seconds.checked_mul(60)?.checked_add(field.parse().ok()?)?;
+            fields += 1;
+        }
+
+        if !(2..=3).contains(&fields) {
+            return None;
+        }
+
+        let millis = match fraction {
+            Some(fraction) if !fraction.is_empty() && fraction.len() <= 3 => {
+                fraction.parse::<u32>().ok()? * 10u32.pow(3 - fraction.len() as u32)
+            }
+            Some(_) => return None,
+            None => 0,
+        };
+
+        Some(Timestamp {
+            millis: seconds.checked_mul(1000)?.checked_add(millis)?,
+        })
+    }
+}
+
+/// One stretch of a transcript said by a single speaker.
+#[derive(Debug, Clone)]
+pub struct Turn {
+    pub speaker: Friend,
+    /// Byte range of the turn in the original transcript, speaker label included.
+    pub span: Range<usize>,
+    pub start: Option<Timestamp>,
+    pub end: Option<Timestamp>,
+}
+
+pub trait TranscriptParser {
+    fn parse(&self, text: &str) -> Vec<Turn>;
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum TranscriptFormat {
+    #[default]
+    Plain,
+    Subtitles,
+    GoogleDocs,
+}
+
+impl TranscriptFormat {
+    /// Guesses the format from a file's extension and contents, falling back to plain text.
+    /// Google Docs starts its plain text downloads with a byte order mark, which is how those are told apart.
+    pub fn detect(path: &Path, text: &str) -> TranscriptFormat {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("vtt") || ext.eq_ignore_ascii_case("srt") => {
+                TranscriptFormat::Subtitles
+            }
+            _ if text.starts_with("WEBVTT") => TranscriptFormat::Subtitles,
+            _ if text.starts_with('\u{feff}') => TranscriptFormat::GoogleDocs,
+            _ => TranscriptFormat::Plain,
+        }
+    }
+
+    pub fn parser(self) -> &'static dyn TranscriptParser {
+        match self {
+            TranscriptFormat::Plain => &PlainParser,
+            TranscriptFormat::Subtitles => &SubtitleParser,
+            TranscriptFormat::GoogleDocs => &GoogleDocsParser,
+        }
+    }
+}
+
+/// Every line is a turn, and the speaker is whoever is named before the first `:`.
+/// Inline `[01:23:45]` markers at the start of a line time the turns that follow them.
+pub struct PlainParser;
+
+impl TranscriptParser for PlainParser {
+    fn parse(&self, text: &str) -> Vec<Turn> {
+        let mut turns = Vec::new();
+        let mut clock = MarkerClock::default();
+
+        for line in text.line_spans() {
+            let content = match leading_marker(&line) {
+                Some((timestamp, rest)) => {
+                    clock.mark(timestamp, &mut turns);
+                    if rest.trim().is_empty() {
+                        continue;
+                    }
+                    rest
+                }
+                None => line.as_str(),
+            };
+
+            turns.push(Turn {
+                speaker: guess_speaker(content).unwrap_or(Friend::Unknown),
+                span: line.range(),
+                start: None,
+                end: None,
+            });
+            clock.stamp(&mut turns);
+        }
+
+        turns
+    }
+}
+
+/// WebVTT and SRT: every cue is a turn, timed by its `start --> end` line.
+/// Speakers come from `<v Name>` voice tags, or a `Name:` label at the start of the cue.
+pub struct SubtitleParser;
+
+impl TranscriptParser for SubtitleParser {
+    fn parse(&self, text: &str) -> Vec<Turn> {
+        let mut turns = Vec::new();
+        let mut lines = text.line_spans().peekable();
+
+        while lines.peek().is_some() {
+            // a cue is a run of non-blank lines; the WEBVTT header, NOTE and STYLE blocks have no timing line and fall through
+            let block: Vec<LineSpan<'_>> = lines
+                .by_ref()
+                .skip_while(|line| line.trim().is_empty())
+                .take_while(|line| !line.trim().is_empty())
+                .collect();
+
+            let Some(timing_idx) = block.iter().position(|line| line.contains("-->")) else {
+                continue;
+            };
+
+            let (start, end) = block[timing_idx]
+                .split_once("-->")
+                .map(|(start, end)| {
+                    (
+                        Timestamp::parse(start),
+                        // cue settings may follow the end time
+                        end.split_whitespace().next().and_then(Timestamp::parse),
+                    )
+                })
+                .unwrap_or_default();
+
+            let payload = &block[timing_idx + 1..];
+            let (Some(first), Some(last)) = (payload.first(), payload.last()) else {
+                continue;
+            };
+
+            let speaker = voice_tag(first)
+                .or_else(|| guess_speaker(first))
+                .unwrap_or(Friend::Unknown);
+
+            turns.push(Turn {
+                speaker,
+                span: first.start()..last.end(),
+                start,
+                end,
+            });
+        }
+
+        turns
+    }
+}
+
+/// Google Docs text exports: a `Name:` label starts a turn, and paragraphs without one continue it.
+/// Lines made up entirely of a bracketed stage direction are turns of their own, with no speaker.
+/// Inline `[01:23:45]` markers time turns the same way as in [`PlainParser`].
+pub struct GoogleDocsParser;
+
+impl TranscriptParser for GoogleDocsParser {
+    fn parse(&self, text: &str) -> Vec<Turn> {
+        let mut turns: Vec<Turn> = Vec::new();
+        let mut clock = MarkerClock::default();
+        let mut continuing = false;
+
+        for line in text.line_spans() {
+            let mut content = line.trim_start_matches('\u{feff}').trim();
+            if let Some((timestamp, rest)) = leading_marker(content) {
+                clock.mark(timestamp, &mut turns);
+                content = rest.trim();
+            }
+
+            if content.is_empty() {
+                continue;
+            }
+
+            if is_stage_direction(content) {
+                turns.push(Turn {
+                    speaker: Friend::Unknown,
+                    span: line.range(),
+                    start: None,
+                    end: None,
+                });
+                clock.stamp(&mut turns);
+                continuing = false;
+                continue;
+            }
+
+            match (speaker_label(content), turns.last_mut()) {
+                (None, Some(turn)) if continuing => turn.span.end = line.end(),
+                (label, _) => {
+                    turns.push(Turn {
+                        speaker: label.map_or(Friend::Unknown, parse_friend),
+                        span: line.range(),
+                        start: None,
+                        end: None,
+                    });
+                    clock.stamp(&mut turns);
+                    continuing = true;
+                }
+            }
+        }
+
+        turns
+    }
+}
+
+/// Times turns from inline markers: every turn after a marker starts at it, and ends at the next one.
+#[derive(Default)]
+struct MarkerClock {
+    current: Option<Timestamp>,
+    pending: Vec<usize>,
+}
+
+impl MarkerClock {
+    fn mark(&mut self, timestamp: Timestamp, turns: &mut [Turn]) {
+        for idx in self.pending.drain(..) {
+            turns[idx].end = Some(timestamp);
+        }
+
+        self.current = Some(timestamp);
+    }
+
+    /// Times the most recently pushed turn.
+    fn stamp(&mut self, turns: &mut [Turn]) {
+        let (Some(current), Some(turn)) = (self.current, turns.last_mut()) else {
+            return;
+        };
+
+        turn.start = Some(current);
+        self.pending.push(turns.len() - 1);
+    }
+}
+
+/// Splits an inline `[01:23:45]` marker off the start of a line.
+fn leading_marker(line: &str) -> Option<(Timestamp, &str)> {
+    let (marker, rest) = line.trim_start().strip_prefix('[')?.split_once(']')?;
+    Some((Timestamp::parse(marker)?, rest))
+}
+
+fn parse_friend(name: &str) -> Friend {
+    Friend::from_str(
+        &name
+            .split_whitespace()
+            .next()
+            .unwrap_or("Unknown")
+            .to_lowercase(),
+    )
+    .unwrap_or(Friend::Unknown)
+}
+
+/// The speaker named before the first `:` of a line, if any.
+pub fn guess_speaker(line: &str) -> Option<Friend> {
+    line.split_once(':').map(|(name, _)| parse_friend(name))
+}
+
+/// The text before a line's first `:`, if it's short enough to plausibly be a name rather than part of a sentence.
+fn speaker_label(line: &str) -> Option<&str> {
+    let (label, _) = line.split_once(':')?;
+    let label = label.trim();
+
+    if label.is_empty()
+        || label.len() > 40
+        || label.split_whitespace().count() > 4
+        || label.contains(['[', ']', '(', ')'])
+    {
+        return None;
+    }
+
+    Some(label)
+}
+
+fn voice_tag(line: &str) -> Option<Friend> {
+    let rest = line.trim_start().strip_prefix("<v")?;
+    let (name, _) = rest.split_once('>')?;
+    // voice tags may carry classes, as in <v.loud Austin>
+    let name = name.split_once(char::is_whitespace).map_or(name, |v| v.1);
+    Some(parse_friend(name))
+}
+
+fn is_stage_direction(line: &str) -> bool {
+    (line.starts_with('[') && line.ends_with(']')) || (line.starts_with('(') && line.ends_with(')'))
+}
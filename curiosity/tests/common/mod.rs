@@ -0,0 +1,10 @@
+use curiosity::store::Store;
+use tempfile::TempDir;
+
+/// Opens a store in a directory of its own, which goes away with the returned [`TempDir`],
+/// including when a test fails.
+pub fn temp_store() -> (Store, TempDir) {
+    let dir = tempfile::tempdir().unwrap();
+    let store = Store::open(dir.path().join("store.redb")).unwrap();
+    (store, dir)
+}
@@ -0,0 +1,236 @@
+mod common;
+
+use std::path::PathBuf;
+
+use common::temp_store;
+use curiosity::{
+    episode_ids::{assign_ids, candidate_id, IdAssignment, IdClash},
+    DownloadOptions, Episode, Season, SeasonId,
+};
+use redb::ReadableTable;
+
+fn season(id: SeasonId, episodes: &[(&str, usize)]) -> Season {
+    Season {
+        title: id.to_string(),
+        id,
+        episodes: episodes
+            .iter()
+            .map(|&(slug, sorting_number)| Episode {
+                title: slug.to_owned(),
+                slug: slug.to_owned(),
+                done: true,
+                sorting_number,
+                docs_id: None,
+                download: Some(DownloadOptions {
+                    plain: PathBuf::from(format!("{slug}.txt")),
+                    format: None,
+                }),
+            })
+            .collect(),
+    }
+}
+
+fn without_download(mut season: Season, slug: &str) -> Season {
+    for episode in season.episodes.iter_mut() {
+        if episode.slug == slug {
+            episode.download = None;
+        }
+    }
+    season
+}
+
+/// Runs [`assign_ids`] once per list of seasons against the same store, returning every assignment
+/// and what ended up persisted.
+fn assign(runs: &[&[Season]]) -> (Vec<IdAssignment>, Vec<(String, u64)>) {
+    let (store, _dir) = temp_store();
+
+    let mut assignments = Vec::new();
+    for seasons in runs {
+        let txn = store.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(store.episode_ids).unwrap();
+            let seasons: Vec<&Season> = seasons.iter().collect();
+            assignments.push(assign_ids(&mut table, &seasons).unwrap());
+        }
+        txn.commit().unwrap();
+    }
+
+    let txn = store.begin_read().unwrap();
+    let table = txn.open_table(store.episode_ids).unwrap();
+    let persisted = table
+        .iter()
+        .unwrap()
+        .map(|entry| {
+            let (slug, id) = entry.unwrap();
+            (slug.value().to_owned(), id.value())
+        })
+        .collect();
+
+    (assignments, persisted)
+}
+
+fn id(season: SeasonId, sorting_number: usize) -> u64 {
+    candidate_id(season, sorting_number).unwrap()
+}
+
+#[test]
+fn new_episodes_get_their_candidate_id() {
+    let (assignments, persisted) =
+        assign(&[&[season(SeasonId::Marielda, &[("one", 1), ("two", 2)])]]);
+
+    let assignment = &assignments[0];
+    assert_eq!(
+        assignment[&(SeasonId::Marielda, 0)].as_ref().unwrap(),
+        &id(SeasonId::Marielda, 1)
+    );
+    assert_eq!(
+        assignment[&(SeasonId::Marielda, 1)].as_ref().unwrap(),
+        &id(SeasonId::Marielda, 2)
+    );
+    assert_eq!(
+        persisted,
+        vec![
+            ("one".to_owned(), id(SeasonId::Marielda, 1)),
+            ("two".to_owned(), id(SeasonId::Marielda, 2)),
+        ]
+    );
+}
+
+#[test]
+fn renumbering_keeps_persisted_ids() {
+    let before = [season(SeasonId::Marielda, &[("four", 4), ("five", 5)])];
+    // "inserted" lands where "five" used to be, and "five" moves up
+    let after = [season(
+        SeasonId::Marielda,
+        &[("four", 4), ("inserted", 5), ("five", 6)],
+    )];
+
+    let (assignments, persisted) = assign(&[&before, &after]);
+
+    let assignment = &assignments[1];
+    assert_eq!(
+        assignment[&(SeasonId::Marielda, 0)].as_ref().unwrap(),
+        &id(SeasonId::Marielda, 4)
+    );
+    assert_eq!(
+        assignment[&(SeasonId::Marielda, 2)].as_ref().unwrap(),
+        &id(SeasonId::Marielda, 5)
+    );
+    // 5 belongs to "five" and 6 is free. ids say nothing about order, positions take care of that
+    assert_eq!(
+        assignment[&(SeasonId::Marielda, 1)].as_ref().unwrap(),
+        &id(SeasonId::Marielda, 6)
+    );
+
+    assert!(persisted.contains(&("five".to_owned(), id(SeasonId::Marielda, 5))));
+    assert!(persisted.contains(&("inserted".to_owned(), id(SeasonId::Marielda, 6))));
+}
+
+#[test]
+fn renumbering_is_stable_across_runs() {
+    let before = [season(SeasonId::Marielda, &[("five", 5)])];
+    let after = [season(SeasonId::Marielda, &[("inserted", 5), ("five", 6)])];
+
+    let (assignments, _) = assign(&[&before, &after, &after]);
+
+    for key in [(SeasonId::Marielda, 0), (SeasonId::Marielda, 1)] {
+        assert_eq!(
+            assignments[1][&key].as_ref().unwrap(),
+            assignments[2][&key].as_ref().unwrap()
+        );
+    }
+}
+
+#[test]
+fn duplicate_slugs_clash() {
+    let (assignments, persisted) = assign(&[&[
+        season(SeasonId::Marielda, &[("one", 1)]),
+        season(SeasonId::Partizan, &[("one", 1)]),
+    ]]);
+
+    let assignment = &assignments[0];
+    assert_eq!(
+        assignment[&(SeasonId::Marielda, 0)].as_ref().unwrap(),
+        &id(SeasonId::Marielda, 1)
+    );
+    assert!(matches!(
+        assignment[&(SeasonId::Partizan, 0)],
+        Err(IdClash::DuplicateSlug { .. })
+    ));
+    assert_eq!(
+        persisted,
+        vec![("one".to_owned(), id(SeasonId::Marielda, 1))]
+    );
+}
+
+#[test]
+fn duplicate_sorting_numbers_clash() {
+    let (assignments, persisted) =
+        assign(&[&[season(SeasonId::Marielda, &[("one", 1), ("also-one", 1)])]]);
+
+    let assignment = &assignments[0];
+    assert_eq!(
+        assignment[&(SeasonId::Marielda, 0)].as_ref().unwrap(),
+        &id(SeasonId::Marielda, 1)
+    );
+    assert!(matches!(
+        &assignment[&(SeasonId::Marielda, 1)],
+        Err(IdClash::DuplicateId { id: clash, .. }) if *clash == id(SeasonId::Marielda, 1)
+    ));
+    assert_eq!(
+        persisted,
+        vec![("one".to_owned(), id(SeasonId::Marielda, 1))]
+    );
+}
+
+#[test]
+fn vanished_slugs_give_up_their_id() {
+    let before = [season(SeasonId::Marielda, &[("old", 1)])];
+    let after = [season(SeasonId::Marielda, &[("new", 1)])];
+
+    let (assignments, persisted) = assign(&[&before, &after]);
+
+    assert_eq!(
+        assignments[1][&(SeasonId::Marielda, 0)].as_ref().unwrap(),
+        &id(SeasonId::Marielda, 1)
+    );
+    assert_eq!(
+        persisted,
+        vec![("new".to_owned(), id(SeasonId::Marielda, 1))]
+    );
+}
+
+#[test]
+fn ids_of_episodes_without_downloads_stay_reserved() {
+    let before = [season(SeasonId::Marielda, &[("old", 1)])];
+    let after = [without_download(
+        season(SeasonId::Marielda, &[("old", 2), ("new", 1)]),
+        "old",
+    )];
+
+    let (assignments, persisted) = assign(&[&before, &after]);
+
+    assert_eq!(
+        assignments[1][&(SeasonId::Marielda, 1)].as_ref().unwrap(),
+        &id(SeasonId::Marielda, 2)
+    );
+    assert!(persisted.contains(&("old".to_owned(), id(SeasonId::Marielda, 1))));
+    assert!(persisted.contains(&("new".to_owned(), id(SeasonId::Marielda, 2))));
+}
+
+#[test]
+fn persisted_and_new_episodes_with_the_same_sorting_number_clash() {
+    let before = [season(SeasonId::Marielda, &[("one", 1)])];
+    let after = [season(SeasonId::Marielda, &[("one", 1), ("also-one", 1)])];
+
+    let (assignments, persisted) = assign(&[&before, &after]);
+
+    assert!(matches!(
+        assignments[1][&(SeasonId::Marielda, 1)],
+        Err(IdClash::DuplicateId { .. })
+    ));
+    assert_eq!(
+        persisted,
+        vec![("one".to_owned(), id(SeasonId::Marielda, 1))]
+    );
+}
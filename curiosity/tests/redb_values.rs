@@ -0,0 +1,100 @@
+mod common;
+
+use std::{borrow::Cow, collections::BTreeMap};
+
+use common::temp_store;
+use curiosity::store::{SentenceList, TermsToSentencesId};
+use proptest::prelude::*;
+use redb::{ReadableTable, RedbValue};
+use zerocopy::AsBytes;
+
+/// Copies `bytes` to `offset` bytes past an 8-byte aligned address and hands them to `f`,
+/// the way redb might hand out a value from the middle of a page.
+fn at_offset<T>(bytes: &[u8], offset: usize, f: impl FnOnce(&[u8]) -> T) -> T {
+    let mut buf = vec![0u64; (offset + bytes.len()) / 8 + 1];
+    let buf = buf.as_bytes_mut();
+    buf[offset..offset + bytes.len()].copy_from_slice(bytes);
+    f(&buf[offset..offset + bytes.len()])
+}
+
+proptest! {
+    #[test]
+    fn sentence_list_round_trips(ids in prop::collection::vec(any::<u32>(), 0..512), offset in 0usize..8) {
+        let list = SentenceList::from_slice(&ids);
+        let bytes = SentenceList::as_bytes(&list).to_vec();
+        prop_assert_eq!(bytes.len(), ids.len() * 4);
+
+        at_offset(&bytes, offset, |data| {
+            let decoded = SentenceList::from_bytes(data);
+            let decoded_ids: Vec<u32> = decoded.ids.iter().map(|id| id.value()).collect();
+            prop_assert_eq!(&decoded_ids, &ids);
+
+            // only copies when it has to
+            let aligned = data.as_ptr().align_offset(4) == 0;
+            prop_assert_eq!(matches!(decoded.ids, Cow::Borrowed(_)), aligned);
+            Ok(())
+        })?;
+    }
+
+    #[test]
+    fn terms_to_sentences_id_round_trips(doc in any::<u64>(), term in any::<u32>(), offset in 0usize..8) {
+        let id = TermsToSentencesId::new(doc, term);
+        let bytes = <TermsToSentencesId as RedbValue>::as_bytes(&&id).to_vec();
+
+        at_offset(&bytes, offset, |data| {
+            let decoded = TermsToSentencesId::from_bytes(data);
+            prop_assert_eq!(decoded.doc(), doc);
+            prop_assert_eq!(decoded.term(), term);
+            Ok(())
+        })?;
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(16))]
+
+    #[test]
+    fn sentence_lists_survive_redb(
+        entries in prop::collection::btree_map(
+            (any::<u64>(), any::<u32>()),
+            prop::collection::vec(any::<u32>(), 0..64),
+            1..64,
+        )
+    ) {
+        let (store, _dir) = temp_store();
+
+        let txn = store.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(store.terms_to_sentences).unwrap();
+            for ((doc, term), ids) in &entries {
+                table
+                    .insert(&TermsToSentencesId::new(*doc, *term), SentenceList::from_slice(ids))
+                    .unwrap();
+            }
+        }
+        txn.commit().unwrap();
+
+        let txn = store.begin_read().unwrap();
+        let table = txn.open_table(store.terms_to_sentences).unwrap();
+        let mut read = BTreeMap::new();
+        for entry in table.iter().unwrap() {
+            let (key, value) = entry.unwrap();
+            let ids: Vec<u32> = value.value().ids.iter().map(|id| id.value()).collect();
+            read.insert((key.value().doc(), key.value().term()), ids);
+        }
+
+        prop_assert_eq!(read, entries);
+    }
+}
+
+#[test]
+#[should_panic(expected = "isn't a whole number of ids")]
+fn sentence_list_rejects_partial_ids() {
+    SentenceList::from_bytes(&[0; 6]);
+}
+
+#[test]
+#[should_panic(expected = "TermsToSentencesId is 12 bytes")]
+fn terms_to_sentences_id_rejects_wrong_length() {
+    TermsToSentencesId::from_bytes(&[0; 11]);
+}
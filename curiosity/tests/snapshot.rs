@@ -0,0 +1,161 @@
+use std::{
+    io::{Cursor, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use curiosity::{
+    db::Db,
+    snapshot::{backup, restore, SnapshotManifest},
+    CuriosityError, DownloadOptions, Episode, Season, SeasonId,
+};
+use sha2::{Digest, Sha256};
+use zip::{write::FileOptions, ZipArchive, ZipWriter};
+
+/// Builds a small data folder in `dir` and returns an archive of it.
+fn snapshot(dir: &Path) -> Vec<u8> {
+    let folder = dir.join("data");
+    let db = Db::new(&folder).unwrap();
+    let seasons = [Season {
+        title: "Marielda".to_owned(),
+        id: SeasonId::Marielda,
+        episodes: ["one", "two"]
+            .iter()
+            .enumerate()
+            .map(|(i, slug)| Episode {
+                title: slug.to_string(),
+                slug: slug.to_string(),
+                done: true,
+                sorting_number: i + 1,
+                docs_id: None,
+                download: Some(DownloadOptions {
+                    plain: PathBuf::from(format!("{slug}.txt")),
+                    format: None,
+                }),
+            })
+            .collect(),
+    }];
+    db.add_documents(&seasons, |_, episode| {
+        Ok(format!("Austin: the dragon in episode {}.", episode.slug))
+    })
+    .unwrap();
+    drop(db);
+
+    let mut out = Cursor::new(Vec::new());
+    backup(&folder, &mut out).unwrap();
+    out.into_inner()
+}
+
+/// Copies `archive`, letting `edit` change any file's contents. The manifest is updated to match
+/// the edits if `rehash` is set, so they only show up once the files are opened.
+fn repack(archive: &[u8], rehash: bool, edit: impl Fn(&str, &mut Vec<u8>)) -> Vec<u8> {
+    let mut archive = ZipArchive::new(Cursor::new(archive)).unwrap();
+    let mut files = Vec::new();
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).unwrap();
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes).unwrap();
+        let name = file.name().to_owned();
+        edit(&name, &mut bytes);
+        files.push((name, bytes));
+    }
+
+    if rehash {
+        let (_, manifest) = files
+            .iter()
+            .find(|(name, _)| name == "manifest.json")
+            .unwrap();
+        let mut manifest: SnapshotManifest = serde_json::from_slice(manifest).unwrap();
+        for entry in manifest.files.iter_mut() {
+            let (_, bytes) = files.iter().find(|(name, _)| *name == entry.path).unwrap();
+            entry.size = bytes.len() as u64;
+            entry.sha256 = Sha256::digest(bytes)
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect();
+        }
+        files.retain(|(name, _)| name != "manifest.json");
+        files.push((
+            "manifest.json".to_owned(),
+            serde_json::to_vec(&manifest).unwrap(),
+        ));
+    }
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, bytes) in files {
+        zip.start_file(name, FileOptions::default()).unwrap();
+        zip.write_all(&bytes).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+}
+
+fn assert_invalid(archive: Vec<u8>, dir: &Path) {
+    let target = dir.join("restored");
+    let result = restore(Cursor::new(archive), &target);
+    assert!(
+        matches!(result, Err(CuriosityError::InvalidSnapshot(_))),
+        "{result:?}"
+    );
+    assert!(!target.exists());
+}
+
+#[test]
+fn backups_restore_into_a_working_db() {
+    let dir = tempfile::tempdir().unwrap();
+    let archive = snapshot(dir.path());
+
+    let target = dir.path().join("restored");
+    let manifest = restore(Cursor::new(archive), &target).unwrap();
+
+    let db = Db::new(&target).unwrap();
+    assert_eq!(db.num_docs(), 2);
+    assert_eq!(db.store.generation().unwrap(), manifest.generation);
+    let query = db.keyword_query("dragon");
+    assert_eq!(
+        db.search(&query, &Default::default(), 10, None)
+            .unwrap()
+            .len(),
+        2
+    );
+}
+
+#[test]
+fn tampered_files_are_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let archive = repack(&snapshot(dir.path()), false, |name, bytes| {
+        if name == "terms.postcard" {
+            bytes[0] ^= 0xff;
+        }
+    });
+
+    assert_invalid(archive, dir.path());
+}
+
+#[test]
+fn manifests_without_the_core_files_are_rejected() {
+    for missing in ["store.redb", "terms.postcard", "index/meta.json"] {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = repack(&snapshot(dir.path()), false, |name, bytes| {
+            if name == "manifest.json" {
+                let mut manifest: SnapshotManifest = serde_json::from_slice(bytes).unwrap();
+                manifest.files.retain(|file| file.path != missing);
+                *bytes = serde_json::to_vec(&manifest).unwrap();
+            }
+        });
+
+        assert_invalid(archive, dir.path());
+    }
+}
+
+#[test]
+fn indexes_with_a_different_schema_are_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let archive = repack(&snapshot(dir.path()), true, |name, bytes| {
+        if name == "index/meta.json" {
+            let mut meta: serde_json::Value = serde_json::from_slice(bytes).unwrap();
+            meta["schema"][0]["name"] = "renamed".into();
+            *bytes = serde_json::to_vec(&meta).unwrap();
+        }
+    });
+
+    assert_invalid(archive, dir.path());
+}
@@ -0,0 +1,188 @@
+use std::path::Path;
+
+use curiosity::{
+    transcript::{
+        GoogleDocsParser, PlainParser, SubtitleParser, Timestamp, TranscriptFormat,
+        TranscriptParser,
+    },
+    Friend,
+};
+
+const PLAIN: &str = include_str!("fixtures/plain.txt");
+const VTT: &str = include_str!("fixtures/subtitles.vtt");
+const SRT: &str = include_str!("fixtures/subtitles.srt");
+const GOOGLE_DOCS: &str = include_str!("fixtures/google_docs.txt");
+
+/// Every turn `parser` finds in `text`, as its speaker, its text and its start and end in milliseconds.
+fn turns<'a>(
+    parser: &dyn TranscriptParser,
+    text: &'a str,
+) -> Vec<(Friend, &'a str, Option<u32>, Option<u32>)> {
+    parser
+        .parse(text)
+        .into_iter()
+        .map(|turn| {
+            (
+                turn.speaker,
+                &text[turn.span],
+                turn.start.map(|t| t.millis),
+                turn.end.map(|t| t.millis),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn plain_lines_are_timed_by_markers() {
+    assert_eq!(
+        turns(&PlainParser, PLAIN),
+        vec![
+            (
+                Friend::Austin,
+                "Austin: Welcome back to the table.",
+                Some(5_000),
+                Some(60_000)
+            ),
+            (
+                Friend::Keith,
+                "Keith: Hi, I'm Keith.",
+                Some(5_000),
+                Some(60_000)
+            ),
+            (
+                Friend::Ali,
+                "[00:01:00] Ali: Picking up where we left off.",
+                Some(60_000),
+                None
+            ),
+            // not a marker, so the line is text and the speaker is whatever comes before the first `:`
+            (
+                Friend::Unknown,
+                "[1:2:3:4] Jack: a marker this broken is just text.",
+                Some(60_000),
+                None
+            ),
+        ]
+    );
+}
+
+#[test]
+fn vtt_cues_are_turns() {
+    assert_eq!(
+        turns(&SubtitleParser, VTT),
+        vec![
+            (
+                Friend::Austin,
+                "<v Austin>Welcome back.</v>",
+                Some(1_000),
+                Some(4_500)
+            ),
+            // the empty cue in between is skipped
+            (
+                Friend::Keith,
+                "Keith: Two lines\nin one cue.",
+                Some(8_250),
+                Some(10_000)
+            ),
+            (
+                Friend::Ali,
+                "<v.loud Ali>A malformed start.",
+                None,
+                Some(12_000)
+            ),
+        ]
+    );
+}
+
+#[test]
+fn srt_cues_are_turns() {
+    assert_eq!(
+        turns(&SubtitleParser, SRT),
+        vec![
+            (Friend::Jack, "Jack: Hello.", Some(1_000), Some(2_000)),
+            (Friend::Sylvi, "Sylvia: Hi, Jack.", Some(3_000), None),
+        ]
+    );
+}
+
+#[test]
+fn google_docs_paragraphs_continue_turns() {
+    assert_eq!(
+        turns(&GoogleDocsParser, GOOGLE_DOCS),
+        vec![
+            (
+                Friend::Austin,
+                "\u{feff}Austin: Welcome back.\r\nThis paragraph carries on with Austin's turn.",
+                None,
+                None
+            ),
+            (Friend::Unknown, "[Laughter]", Some(120_000), Some(180_000)),
+            (
+                Friend::Keith,
+                "Keith: A new turn.",
+                Some(120_000),
+                Some(180_000)
+            ),
+            (
+                Friend::Ali,
+                "[00:03:00] Ali: Timed by an inline marker.",
+                Some(180_000),
+                None
+            ),
+            // not a marker, so it's kept as a stage direction
+            (Friend::Unknown, "[1:2:3:4]", Some(180_000), None),
+            (
+                Friend::Unknown,
+                "Dre: Not one of the friends.",
+                Some(180_000),
+                None
+            ),
+        ]
+    );
+}
+
+#[test]
+fn empty_transcripts_have_no_turns() {
+    for parser in [
+        &PlainParser as &dyn TranscriptParser,
+        &SubtitleParser,
+        &GoogleDocsParser,
+    ] {
+        assert!(parser.parse("").is_empty());
+    }
+    assert!(SubtitleParser
+        .parse("WEBVTT\n\n1\n00:00:01.000 --> 00:00:02.000\n")
+        .is_empty());
+}
+
+#[test]
+fn timestamps_parse() {
+    let millis = |s| Timestamp::parse(s).map(|t| t.millis);
+
+    assert_eq!(millis("01:02:03"), Some(3_723_000));
+    assert_eq!(millis("02:03.5"), Some(123_500));
+    assert_eq!(millis("00:00:01,250"), Some(1_250));
+
+    for malformed in [
+        "",
+        "12",
+        "1:2:3:4",
+        "00:00:bad",
+        "00:01.",
+        "00:01.2345",
+        "1:123",
+    ] {
+        assert_eq!(millis(malformed), None, "{malformed:?}");
+    }
+}
+
+#[test]
+fn formats_are_detected_from_extension_and_contents() {
+    let detect = |path, text| TranscriptFormat::detect(Path::new(path), text);
+
+    assert_eq!(detect("ep.vtt", ""), TranscriptFormat::Subtitles);
+    assert_eq!(detect("ep.SRT", ""), TranscriptFormat::Subtitles);
+    assert_eq!(detect("ep.txt", VTT), TranscriptFormat::Subtitles);
+    assert_eq!(detect("ep.txt", GOOGLE_DOCS), TranscriptFormat::GoogleDocs);
+    assert_eq!(detect("ep.txt", PLAIN), TranscriptFormat::Plain);
+}
@@ -4,7 +4,8 @@ export type EpisodeData = {
     season: string,
     title: string,
     docs_id: string,
-    highlights: { text: string, highlighted: boolean }[][]
+    highlights: { text: string, highlighted: boolean }[][],
+    highlight_times: ({ start: number | null, end: number | null } | null)[]
 }
 
 export enum QueryKind  {
@@ -24,5 +25,6 @@ export interface ApiRequest  {
 
 export type ApiResponse = {
     next_page: string | null,
-    episodes: EpisodeData[]
+    episodes: EpisodeData[],
+    truncated: boolean
 }
\ No newline at end of file
@@ -0,0 +1,127 @@
+use actix_web::{dev::Payload, http::header, web, FromRequest, HttpRequest, HttpResponse};
+use futures_util::future::{ready, Ready};
+use subtle::ConstantTimeEq;
+
+use curiosity::db::Db;
+use curiosity::report::unix_millis;
+use curiosity::snapshot::SnapshotManifest;
+
+use crate::api::cache::SearchCache;
+use crate::config::Config;
+use crate::update::{UpdateTrigger, Updater};
+use crate::{ServerError, ServerResult};
+
+/// Proof that a request carried `Authorization: Bearer <CURIOSITY_ADMIN_TOKEN>`.
+/// Taking it as an argument is what puts an endpoint behind the admin token.
+pub struct AdminToken;
+
+impl FromRequest for AdminToken {
+    type Error = ServerError;
+    type Future = Ready<ServerResult<AdminToken>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let Some(expected) = req
+            .app_data::<web::Data<Config>>()
+            .and_then(|config| config.admin_token.as_deref())
+        else {
+            return ready(Err(ServerError::AdminDisabled));
+        };
+
+        let given = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .unwrap_or("");
+
+        if bool::from(given.as_bytes().ct_eq(expected.as_bytes())) {
+            ready(Ok(AdminToken))
+        } else {
+            ready(Err(ServerError::Unauthorized))
+        }
+    }
+}
+
+#[actix_web::get("/admin/ingestion-report")]
+pub async fn ingestion_report(_: AdminToken, db: web::Data<Db>) -> ServerResult<HttpResponse> {
+    let report = db.store.latest_report()?;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[actix_web::get("/admin/search-cache")]
+pub async fn search_cache_stats(_: AdminToken, cache: web::Data<SearchCache>) -> HttpResponse {
+    HttpResponse::Ok().json(cache.stats())
+}
+
+#[derive(serde::Serialize)]
+struct StartedUpdate {
+    id: u64,
+}
+
+/// Starts an update in the background. Responds 409 if one is already running.
+#[actix_web::post("/admin/updates")]
+pub async fn trigger_update(
+    _: AdminToken,
+    updater: web::Data<Updater>,
+) -> ServerResult<HttpResponse> {
+    let id = Updater::spawn(updater, UpdateTrigger::Manual)?;
+    Ok(HttpResponse::Accepted().json(StartedUpdate { id }))
+}
+
+/// Past updates, newest first, with how long they took and what went wrong. Kept across restarts.
+#[actix_web::get("/admin/updates")]
+pub async fn update_history(_: AdminToken, updater: web::Data<Updater>) -> HttpResponse {
+    HttpResponse::Ok().json(updater.history())
+}
+
+/// The update in progress and how far along it is, or `null`.
+#[actix_web::get("/admin/updates/current")]
+pub async fn current_update(_: AdminToken, updater: web::Data<Updater>) -> HttpResponse {
+    HttpResponse::Ok().json(updater.current())
+}
+
+/// Stops the update in progress before it commits anything. Responds 409 if none is running.
+#[actix_web::post("/admin/updates/current/cancel")]
+pub async fn cancel_update(
+    _: AdminToken,
+    updater: web::Data<Updater>,
+) -> ServerResult<HttpResponse> {
+    let id = updater.cancel()?;
+    Ok(HttpResponse::Accepted().json(StartedUpdate { id }))
+}
+
+#[derive(serde::Serialize)]
+struct Backup {
+    path: String,
+    manifest: SnapshotManifest,
+}
+
+/// Writes a snapshot archive of the data folder to the backup directory. Waits for an update in progress to finish.
+/// Restoring one is done offline, with `server restore <archive>`.
+#[actix_web::post("/admin/backups")]
+pub async fn create_backup(
+    _: AdminToken,
+    db: web::Data<Db>,
+    config: web::Data<Config>,
+) -> ServerResult<HttpResponse> {
+    let backup = actix_web::rt::task::spawn_blocking(move || -> ServerResult<Backup> {
+        std::fs::create_dir_all(&config.backup_dir)?;
+        let name = format!("curiosity-{}-{}.zip", db.store.generation()?, unix_millis());
+        let path = config.backup_dir.join(name);
+
+        // written under another name first, so a half-written archive never looks like a backup
+        let partial = path.with_extension("zip.partial");
+        let manifest = db.snapshot(std::fs::File::create(&partial)?)?;
+        std::fs::rename(&partial, &path)?;
+
+        Ok(Backup {
+            path: path.display().to_string(),
+            manifest,
+        })
+    })
+    .await
+    .unwrap()?;
+
+    tracing::info!(path = backup.path, "wrote backup");
+    Ok(HttpResponse::Created().json(backup))
+}
@@ -0,0 +1,57 @@
+use std::time::Instant;
+
+use crate::config::SearchLimits;
+
+/// Tracks how much highlighting a response has left before it has to stop.
+pub(crate) struct Budget {
+    highlights_left: usize,
+    sentences_left: usize,
+    deadline: Option<Instant>,
+    truncated: bool,
+}
+
+impl Budget {
+    pub fn new(limits: &SearchLimits, started: Instant) -> Budget {
+        Budget {
+            highlights_left: limits.max_highlights,
+            sentences_left: limits.max_sentences_scanned,
+            deadline: started.checked_add(limits.timeout),
+            truncated: false,
+        }
+    }
+
+    pub fn timed_out(&self) -> bool {
+        matches!(self.deadline, Some(deadline) if Instant::now() >= deadline)
+    }
+
+    /// Accounts for looking at one more sentence; false once that's over budget.
+    pub fn scan(&mut self) -> bool {
+        if self.truncated {
+            return false;
+        }
+
+        if self.sentences_left == 0 || self.timed_out() {
+            self.truncated = true;
+            return false;
+        }
+
+        self.sentences_left -= 1;
+        true
+    }
+
+    /// Accounts for one more highlight; false once that's over budget.
+    pub fn highlight(&mut self) -> bool {
+        if self.highlights_left == 0 {
+            self.truncated = true;
+            return false;
+        }
+
+        self.highlights_left -= 1;
+        true
+    }
+
+    /// Whether anything was left out of the response to stay within budget.
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+}
@@ -0,0 +1,132 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use actix_web::web::Bytes;
+use lru::LruCache;
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use crate::api::stream::ChunkSink;
+use crate::api::types::{QueryKind, SearchRequest};
+use crate::{metrics, ServerResult};
+
+/// Responses bigger than this are streamed as usual but not kept.
+const MAX_CACHED_RESPONSE: usize = 1_000_000;
+
+/// Recently served `/search` responses, keyed by the normalized request, the page's cursor and the index generation.
+pub struct SearchCache {
+    responses: Mutex<LruCache<Vec<u8>, Bytes>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Serialize)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub capacity: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl SearchCache {
+    /// A capacity of 0 turns caching off.
+    pub fn new(capacity: usize) -> SearchCache {
+        SearchCache {
+            responses: Mutex::new(LruCache::new(capacity)),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Two requests that only differ in spacing, letter case or season order share a key.
+    pub fn key(
+        query: &SearchRequest,
+        after: Option<u64>,
+        generation: u64,
+    ) -> ServerResult<Vec<u8>> {
+        let mut query = query.clone();
+        query.page = None;
+        query.query = query
+            .query
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .into();
+        // web queries have case-sensitive operators, everything else is lowercased by the tokenizer anyway
+        if !matches!(query.kind, QueryKind::Web) {
+            query.query = query.query.to_lowercase().into();
+        }
+
+        for seasons in [&mut query.seasons, &mut query.exclude_seasons] {
+            seasons.sort_unstable();
+            seasons.dedup();
+        }
+        query.exclude_speakers.sort_unstable();
+        query.exclude_speakers.dedup();
+
+        Ok(postcard::to_stdvec(&(generation, after, query))?)
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<Bytes> {
+        let response = self.responses.lock().get(key).cloned();
+        let (counter, result) = match response {
+            Some(_) => (&self.hits, "hit"),
+            None => (&self.misses, "miss"),
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        metrics::SEARCH_CACHE.with_label_values(&[result]).inc();
+
+        response
+    }
+
+    pub fn put(&self, key: Vec<u8>, response: Bytes) {
+        self.responses.lock().put(key, response);
+    }
+
+    pub fn clear(&self) {
+        self.responses.lock().clear();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let responses = self.responses.lock();
+        CacheStats {
+            entries: responses.len(),
+            capacity: responses.cap(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Forwards chunks to a [`ChunkSink`] while keeping a copy of them, until the copy gets too big to cache.
+pub(crate) struct RecordingSink<'s> {
+    sink: &'s ChunkSink,
+    recorded: Option<Vec<u8>>,
+}
+
+impl<'s> RecordingSink<'s> {
+    pub fn new(sink: &'s ChunkSink) -> RecordingSink<'s> {
+        RecordingSink {
+            sink,
+            recorded: Some(Vec::with_capacity(4096)),
+        }
+    }
+
+    pub fn send(&mut self, chunk: impl Into<Bytes>) -> ServerResult<()> {
+        let chunk = chunk.into();
+
+        if let Some(recorded) = self.recorded.as_mut() {
+            if recorded.len() + chunk.len() > MAX_CACHED_RESPONSE {
+                self.recorded = None;
+            } else {
+                recorded.extend_from_slice(&chunk);
+            }
+        }
+
+        self.sink.send(chunk)
+    }
+
+    /// The whole response, if it was small enough to keep.
+    pub fn finish(self) -> Option<Bytes> {
+        self.recorded.map(Bytes::from)
+    }
+}
@@ -0,0 +1,62 @@
+use actix_web::{http::StatusCode, web, HttpResponse, HttpResponseBuilder};
+
+use curiosity::db::Db;
+use curiosity::docs_accessor::{DocsAccessor, DocumentGuard};
+use curiosity::CuriosityError;
+
+use nyoom_json::{Serializer, UnescapedStr};
+
+use crate::{ServerError, ServerResult};
+
+macro_rules! noescape {
+    ($l:expr) => {
+        UnescapedStr::create($l)
+    };
+}
+
+#[actix_web::get("/episode/{slug}")]
+pub async fn episode(slug: web::Path<String>, db: web::Data<Db>) -> ServerResult<HttpResponse> {
+    let slug = slug.into_inner();
+    let Some(id) = db.store.episode_id(&slug)? else {
+        return Err(ServerError::UnknownEpisode(slug));
+    };
+
+    let txn = db.store.begin_read()?;
+    let mut ep_db = db.store.get_docs_accessor(txn.get())?;
+    // the slug may have an id from an earlier run without being indexed right now
+    let mut doc_reader = match ep_db.get_doc(id) {
+        Err(CuriosityError::NotFound) => return Err(ServerError::UnknownEpisode(slug)),
+        doc_reader => doc_reader?,
+    };
+    let doc = doc_reader.try_read_doc()?;
+
+    let mut out = String::with_capacity(doc.text.len() + doc.tokens.len() * 64);
+    let mut ser = Serializer::new(&mut out);
+    let mut episode = ser.object();
+    episode.field(noescape!("curiosity_id"), doc.id.value());
+    episode.field(noescape!("slug"), doc.slug.as_str());
+    episode.field(noescape!("title"), doc.title.as_str());
+    if let Some(docs_id) = doc.docs_id.as_ref() {
+        episode.field("docs_id", docs_id.as_str());
+    }
+
+    episode.field("season", noescape!(doc.season.as_ref()));
+
+    let mut lines = episode.array_field(noescape!("lines"));
+    for sentence in doc.tokens.iter() {
+        let (start, end) = sentence.timestamps();
+
+        let mut line = lines.add_object();
+        line.field(noescape!("speaker"), noescape!(sentence.author.as_ref()));
+        line.field(noescape!("text"), sentence.get(&doc.text));
+        line.field(noescape!("start"), start);
+        line.field(noescape!("end"), end);
+    }
+    lines.end();
+
+    episode.end();
+
+    Ok(HttpResponseBuilder::new(StatusCode::OK)
+        .content_type("application/json")
+        .body(out))
+}
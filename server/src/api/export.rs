@@ -0,0 +1,253 @@
+use std::io::Write;
+use std::time::Instant;
+
+use actix_web::{http::header, web, HttpResponse};
+
+use curiosity::db::Db;
+use curiosity::docs_accessor::{DocsAccessor, DocumentGuard};
+use curiosity::sentence::SentencePart;
+
+use serde::{Deserialize, Serialize};
+
+use crate::api::budget::Budget;
+use crate::api::search::{build_filters, build_query, for_each_highlight};
+use crate::api::stream::{stream_blocking, ChunkSink};
+use crate::api::types::SearchRequest;
+use crate::config::Config;
+use crate::{metrics, ServerError, ServerResult};
+
+/// Episodes fetched from the index at a time while exporting.
+const EXPORT_BATCH_SIZE: usize = 100;
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Jsonl,
+    Markdown,
+}
+
+impl ExportFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Jsonl => "application/x-ndjson",
+            ExportFormat::Markdown => "text/markdown; charset=utf-8",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Jsonl => "jsonl",
+            ExportFormat::Markdown => "md",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ExportOptions {
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+#[derive(Serialize)]
+struct ExportRow<'a> {
+    episode: &'a str,
+    season: &'a str,
+    slug: &'a str,
+    speaker: &'a str,
+    line: usize,
+    text: String,
+    highlighted: String,
+}
+
+/// Streams every result of a search, one row per highlighted sentence, ignoring paging.
+/// Bounded by [`Config::export_limits`]; if one of them cuts the export short, or it fails partway,
+/// it ends with a line saying so. It also fails if the index is updated while it runs.
+#[actix_web::get("/search/export")]
+pub async fn export(
+    query: web::Query<SearchRequest>,
+    options: web::Query<ExportOptions>,
+    db: web::Data<Db>,
+    config: web::Data<Config>,
+) -> ServerResult<HttpResponse> {
+    let started = Instant::now();
+    let query = query.into_inner();
+    let format = options.format;
+    let (parsed_query, is_phrase_query) = build_query(&db, &query, &config.limits)?;
+    let filters = build_filters(&db, &query)?;
+
+    let body = stream_blocking(move |sink| {
+        write_header(format, sink)?;
+
+        let mut budget = Budget::new(&config.export_limits, started);
+        let mut write_rows = || -> ServerResult<()> {
+            let txn = db.store.begin_read()?;
+            let sentences_db = txn.open_table(db.store.terms_to_sentences)?;
+            let mut ep_db = db.store.get_docs_accessor(txn.get())?;
+            let generation = ep_db.generation();
+
+            let mut after = None;
+            'batches: loop {
+                let index_timer = metrics::INDEX_SEARCH_DURATION.start_timer();
+                let results = db.search(&parsed_query, &filters, EXPORT_BATCH_SIZE, after)?;
+                index_timer.observe_duration();
+                after = results.last().map(|last| last.position);
+
+                // every batch searches the index as it is now, while documents come from the transaction above.
+                // the store is committed before the index, so an index that moved on shows up here first
+                if db.store.generation()? != generation {
+                    return Err(ServerError::IndexUpdated);
+                }
+
+                for hit in results.iter() {
+                    let mut doc_reader = ep_db.get_doc(hit.episode_id)?;
+                    let doc = doc_reader.try_read_doc()?;
+
+                    let mut out = Vec::new();
+                    let mut write_result = Ok(());
+                    for_each_highlight(
+                        &sentences_db,
+                        doc,
+                        &parsed_query.terms,
+                        is_phrase_query,
+                        &query.exclude_speakers,
+                        &mut budget,
+                        |sentence, highlighted| {
+                            if write_result.is_err() {
+                                return;
+                            }
+
+                            let parts = highlighted.parts();
+                            let row = ExportRow {
+                                episode: doc.title.as_str(),
+                                season: doc.season.as_ref(),
+                                slug: doc.slug.as_str(),
+                                speaker: sentence.author.as_ref(),
+                                line: sentence.line_number(&doc.text),
+                                text: parts.iter().map(SentencePart::text).collect(),
+                                highlighted: SentencePart::display_string(parts),
+                            };
+
+                            write_result = write_row(format, &row, &mut out);
+                        },
+                    )?;
+                    write_result?;
+
+                    if !out.is_empty() {
+                        sink.send(out)?;
+                    }
+
+                    if budget.truncated() {
+                        break 'batches;
+                    }
+                }
+
+                if results.len() < EXPORT_BATCH_SIZE {
+                    break;
+                }
+            }
+
+            Ok(())
+        };
+
+        if let Err(e) = write_rows() {
+            // the status went out with the header, so the export has to say it's incomplete itself
+            if !matches!(e, ServerError::StreamClosed) {
+                let _ = write_failed(format, &e, sink);
+            }
+            return Err(e);
+        }
+
+        if budget.truncated() {
+            write_truncated(format, sink)?;
+        }
+
+        Ok(())
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"curiosity-export.{}\"",
+                format.extension()
+            ),
+        ))
+        .streaming(body))
+}
+
+fn write_header(format: ExportFormat, sink: &ChunkSink) -> ServerResult<()> {
+    match format {
+        ExportFormat::Csv => sink.send("episode,season,slug,speaker,line,text,highlighted\n"),
+        ExportFormat::Jsonl => Ok(()),
+        ExportFormat::Markdown => sink.send(
+            "| Episode | Season | Slug | Speaker | Line | Text | Highlighted |\n\
+             | --- | --- | --- | --- | --- | --- | --- |\n",
+        ),
+    }
+}
+
+/// Marks an export cut short by its limits, in a way that doesn't trip up readers of `format`:
+/// a `#` comment for CSV, an object of its own for JSON Lines, and a closing paragraph for Markdown.
+fn write_truncated(format: ExportFormat, sink: &ChunkSink) -> ServerResult<()> {
+    const MESSAGE: &str =
+        "export truncated: it hit the limit on sentences scanned, highlights or time";
+
+    match format {
+        ExportFormat::Csv => sink.send(format!("# {MESSAGE}\n")),
+        ExportFormat::Jsonl => sink.send("{\"truncated\":true}\n"),
+        ExportFormat::Markdown => sink.send(format!("\n_{MESSAGE}_\n")),
+    }
+}
+
+/// Marks an export that failed partway, the same way [`write_truncated`] does.
+fn write_failed(format: ExportFormat, error: &ServerError, sink: &ChunkSink) -> ServerResult<()> {
+    match format {
+        ExportFormat::Csv => sink.send(format!("# export failed: {error}\n")),
+        ExportFormat::Jsonl => {
+            let mut line = serde_json::to_vec(&serde_json::json!({ "error": error.to_string() }))?;
+            line.push(b'\n');
+            sink.send(line)
+        }
+        ExportFormat::Markdown => sink.send(format!("\n_export failed: {error}_\n")),
+    }
+}
+
+fn write_row(format: ExportFormat, row: &ExportRow<'_>, out: &mut Vec<u8>) -> ServerResult<()> {
+    match format {
+        ExportFormat::Csv => {
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(out);
+            writer.serialize(row)?;
+            writer.flush()?;
+        }
+        ExportFormat::Jsonl => {
+            serde_json::to_writer(&mut *out, row)?;
+            out.push(b'\n');
+        }
+        ExportFormat::Markdown => {
+            writeln!(
+                out,
+                "| {} | {} | {} | {} | {} | {} | {} |",
+                markdown_cell(row.episode),
+                row.season,
+                row.slug,
+                row.speaker,
+                row.line,
+                markdown_cell(&row.text),
+                markdown_cell(&row.highlighted),
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+fn markdown_cell(text: &str) -> String {
+    text.replace('|', "\\|").replace(['\r', '\n'], " ")
+}
@@ -1,3 +1,18 @@
+mod admin;
+mod budget;
+pub mod cache;
+#[allow(hidden_glob_reexports)]
+mod episode;
+#[allow(hidden_glob_reexports)]
+mod export;
+pub mod page_token;
+#[allow(hidden_glob_reexports)]
 mod search;
+mod stream;
 pub mod types;
+mod webhook;
+pub use admin::*;
+pub use episode::*;
+pub use export::*;
 pub use search::*;
+pub use webhook::*;
@@ -1,15 +1,26 @@
+use std::time::Instant;
+
 use actix_web::{http::StatusCode, web, HttpResponse, HttpResponseBuilder};
 
-use curiosity::db::Db;
-use curiosity::docs_accessor::{DocsAccessor, DocumentGuard};
-use curiosity::store::TermsToSentencesId;
+use curiosity::db::{BoxedQuery, Db, SearchHit};
+use curiosity::docs_accessor::{DocsAccessor, DocumentGuard, SimpleDocsAccessor};
+use curiosity::filters::SearchFilters;
+use curiosity::sentence::{ArchivedSentence, HighlightedSentence};
+use curiosity::store::{SentenceList, TermsToSentencesId};
+use curiosity::{ArchivedStoredEpisode, Friend};
 
 use nyoom_json::{Serializer, UnescapedStr};
 use redb::ReadableTable;
 
+use crate::api::budget::Budget;
+use crate::api::cache::{RecordingSink, SearchCache};
+use crate::api::page_token::PageToken;
+use crate::api::stream::stream_blocking;
 use crate::api::types::{QueryKind, SearchRequest};
-use crate::{ServerError, ServerResult};
+use crate::config::{Config, SearchLimits};
+use crate::{metrics, ServerError, ServerResult};
 
+use smallvec::SmallVec;
 use tinyset::SetU32;
 
 macro_rules! noescape {
@@ -23,105 +34,461 @@ macro_rules! noescape {
 pub async fn search(
     query: web::Query<SearchRequest>,
     db: web::Data<Db>,
+    config: web::Data<Config>,
+    cache: web::Data<SearchCache>,
 ) -> ServerResult<HttpResponse> {
+    let started = Instant::now();
     let query = query.into_inner();
+    let generation = db.store.generation()?;
 
-    let mut query = if let Some(page) = query.page.as_ref().filter(|page| page.as_str() != "null") {
-        let mut out = Vec::with_capacity(128);
-        base64_url::decode_to_vec(&page, &mut out).map_err(|_| ServerError::BadPageToken)?;
-        postcard::from_bytes(&out)?
-    } else {
-        query
-    };
-
-    let (parsed_query, is_phrase_query) = match query.kind {
-        QueryKind::Phrase if query.query.split_ascii_whitespace().take(2).count() >= 2 => {
-            (db.phrase_query(&query.query).boxed(), true)
+    let (query, after) = match query.page.as_ref().filter(|page| page.as_str() != "null") {
+        Some(page) => {
+            let token = PageToken::decode(page, &config.page_secret, generation)?;
+            (token.request, Some(token.after))
         }
-        QueryKind::Web => (db.parse_query(&query.query)?.boxed(), false),
-        _ => (db.keyword_query(&query.query).boxed(), false),
+        None => (query, None),
     };
 
+    let cache_key = SearchCache::key(&query, after, generation)?;
+    if let Some(response) = cache.get(&cache_key) {
+        metrics::SEARCH_DURATION
+            .with_label_values(&[query.kind.label()])
+            .observe(started.elapsed().as_secs_f64());
+        tracing::info!(
+            kind = query.kind.label(),
+            cached = true,
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "search"
+        );
+        return Ok(HttpResponseBuilder::new(StatusCode::OK)
+            .content_type("application/json")
+            .body(response));
+    }
+
+    let (parsed_query, is_phrase_query) = build_query(&db, &query, &config.limits)?;
+
     // let page_size = 1;
     let page_size = std::cmp::min(100, query.page_size);
 
-    let results = db.search(
+    // one extra hit tells us whether there's a next page, so the last page never comes back empty
+    let mut budget = Budget::new(&config.limits, started);
+    let index_timer = metrics::INDEX_SEARCH_DURATION.start_timer();
+    let mut results = find_episodes(
+        &db,
+        generation,
+        &query,
         &parsed_query,
-        query.seasons.clone(),
-        page_size,
-        query._curiosity_internal_offset,
+        is_phrase_query,
+        page_size + 1,
+        after,
+        &mut budget,
     )?;
-    let next_page = if results.len() >= page_size {
-        query._curiosity_internal_offset += results.len();
-        Some(base64_url::encode(&postcard::to_stdvec(&query)?))
-    } else {
-        None
+    index_timer.observe_duration();
+    let has_next_page = results.len() > page_size;
+    results.truncate(page_size);
+    let result_count = results.len();
+    metrics::SEARCH_RESULTS.observe(result_count as f64);
+
+    if budget.timed_out() {
+        return Err(ServerError::SearchTimeout(
+            config.limits.timeout.as_millis(),
+        ));
+    }
+
+    let next_page = match results.last() {
+        Some(last) if has_next_page => Some(
+            PageToken {
+                generation,
+                after: last.position,
+                request: &query,
+            }
+            .encode(&config.page_secret)?,
+        ),
+        _ => None,
     };
 
-    let mut out = String::with_capacity(50_000);
-    let mut ser = Serializer::new(&mut out);
-    let mut response_obj = ser.object();
-    response_obj.field(UnescapedStr::create("next_page"), next_page.as_deref());
+    let kind = query.kind;
+    let body = stream_blocking(move |sink| {
+        let mut sink = RecordingSink::new(sink);
+
+        let mut prefix = String::with_capacity(256);
+        prefix.push_str("{\"next_page\":");
+        Serializer::new(&mut prefix).write(next_page.as_deref());
+        prefix.push_str(",\"episodes\":[");
+        sink.send(prefix)?;
+
+        let mut failed = None;
+        for (idx, hit) in results.iter().enumerate() {
+            let mut out = String::with_capacity(4096);
+            if idx > 0 {
+                out.push(',');
+            }
+
+            // a read transaction per episode, dropped before sending so a slow client can't keep it open
+            let written = render_episode(
+                &mut out,
+                &db,
+                generation,
+                hit.episode_id,
+                &parsed_query,
+                is_phrase_query,
+                &query,
+                &mut budget,
+            );
+            if let Err(e) = written {
+                failed = Some(e);
+                break;
+            }
+            sink.send(out)?;
+        }
+
+        // goes last so it can account for everything streamed before it.
+        // the status has gone out already, so an error ends up here too, keeping the body valid JSON
+        let mut suffix = String::from("]");
+        if let Some(e) = failed.as_ref() {
+            suffix.push_str(",\"error\":");
+            Serializer::new(&mut suffix).write(e.to_string().as_str());
+        }
+        suffix.push_str(",\"truncated\":");
+        Serializer::new(&mut suffix).write(budget.truncated() || failed.is_some());
+        suffix.push('}');
+        sink.send(suffix)?;
+
+        if let Some(e) = failed {
+            return Err(e);
+        }
+
+        // truncation can depend on how long things took, so only complete responses are reused
+        if let Some(response) = sink.finish().filter(|_| !budget.truncated()) {
+            cache.put(cache_key, response);
+        }
+
+        Ok(())
+    });
+
+    metrics::SEARCH_DURATION
+        .with_label_values(&[kind.label()])
+        .observe(started.elapsed().as_secs_f64());
+    // highlights are streamed afterwards, so this only covers the index search
+    tracing::info!(
+        kind = kind.label(),
+        cached = false,
+        results = result_count,
+        elapsed_ms = started.elapsed().as_millis() as u64,
+        "search"
+    );
+
+    Ok(HttpResponseBuilder::new(StatusCode::OK)
+        .content_type("application/json")
+        .streaming(body))
+}
+
+/// Finds up to `limit` episodes after `after`, like [`Db::search`]. While excluded speakers' lines are only
+/// being hidden, episodes where those lines were the only matches are skipped, since they'd come back empty.
+#[allow(clippy::too_many_arguments)]
+fn find_episodes(
+    db: &Db,
+    generation: u64,
+    query: &SearchRequest,
+    parsed_query: &BoxedQuery,
+    is_phrase_query: bool,
+    limit: usize,
+    mut after: Option<u64>,
+    budget: &mut Budget,
+) -> ServerResult<Vec<SearchHit>> {
+    let filters = build_filters(db, query)?;
+    if query.exclude_speakers.is_empty() || query.exclude_speaker_episodes {
+        return Ok(db.search(parsed_query, &filters, limit, after)?);
+    }
 
     let txn = db.store.begin_read()?;
     let sentences_db = txn.open_table(db.store.terms_to_sentences)?;
-    let mut ep_db = db.store.get_docs_accessor(txn.get())?;
-
-    let mut episodes = response_obj.array_field("episodes");
-    for (doc_id, _) in results {
-        let mut doc_reader = ep_db.get_doc(doc_id.0)?;
-        let doc = doc_reader.read_doc();
-
-        let mut episode = episodes.add_object();
-        episode.field(noescape!("curiosity_id"), doc.id.value());
-        episode.field(noescape!("slug"), doc.slug.as_str());
-        episode.field(noescape!("title"), doc.title.as_str());
-        if let Some(docs_id) = doc.docs_id.as_ref() {
-            episode.field("docs_id", docs_id.as_str());
+    let ep_db = db.store.get_docs_accessor(txn.get())?;
+    let mut ep_db = same_generation(ep_db, generation)?;
+
+    let mut found = Vec::with_capacity(limit);
+    loop {
+        let batch = db.search(parsed_query, &filters, limit, after)?;
+        after = batch.last().map(|last| last.position);
+
+        for &hit in batch.iter() {
+            let mut doc_reader = ep_db.get_doc(hit.episode_id)?;
+            let doc = doc_reader.try_read_doc()?;
+            let visible = has_visible_match(
+                &sentences_db,
+                doc,
+                &parsed_query.terms,
+                is_phrase_query,
+                &query.exclude_speakers,
+                budget,
+            )?;
+
+            if visible {
+                found.push(hit);
+                if found.len() == limit {
+                    return Ok(found);
+                }
+            }
+        }
+
+        if batch.len() < limit || budget.truncated() {
+            return Ok(found);
         }
+    }
+}
 
-        episode.field("season", noescape!(doc.season.as_ref()));
+/// Whether `doc` matches `terms` anywhere but in the lines of `exclude_speakers`. Matches outside the transcript,
+/// like in the title, count. Once `budget` runs out, episodes get the benefit of the doubt.
+fn has_visible_match(
+    sentences_db: &impl ReadableTable<TermsToSentencesId, SentenceList<'static>>,
+    doc: &ArchivedStoredEpisode,
+    terms: &[u32],
+    is_phrase_query: bool,
+    exclude_speakers: &[Friend],
+    budget: &mut Budget,
+) -> ServerResult<bool> {
+    let mut term_to_sentence_id = TermsToSentencesId::new(doc.id.value(), 0);
+    let mut in_transcript = false;
 
-        if !query.highlight {
-            episode.end();
+    for term in terms.iter() {
+        term_to_sentence_id.set_term(*term);
+        let Some(sentence_ids) = sentences_db.get(&term_to_sentence_id)? else {
             continue;
+        };
+
+        for sentence_id in sentence_ids.value().ids.iter() {
+            in_transcript = true;
+            if !budget.scan() {
+                return Ok(true);
+            }
+
+            let sentence = &doc.tokens[sentence_id.value() as usize];
+            if !said_by(sentence, exclude_speakers)
+                && sentence
+                    .highlight(terms, &doc.text, is_phrase_query)
+                    .is_some()
+            {
+                return Ok(true);
+            }
         }
+    }
 
-        let mut term_to_sentence_id = TermsToSentencesId::new(doc_id.0, 0);
+    Ok(!in_transcript)
+}
 
-        let mut highlights = episode.array_field(noescape!("highlights"));
-        let mut seen_sentences: SetU32 = SetU32::new();
+fn said_by(sentence: &ArchivedSentence, speakers: &[Friend]) -> bool {
+    speakers
+        .iter()
+        .any(|&speaker| speaker as u8 == sentence.author as u8)
+}
 
-        for term in parsed_query.terms.iter() {
-            term_to_sentence_id.set_term(*term);
-            let Some(sentence_ids) = sentences_db.get(&term_to_sentence_id)? else {
-                continue;
-            };
+/// Loads episode `doc_id` in a transaction of its own and serializes it into `out`.
+/// Fails if the store has moved on from `generation`, which the results were found in.
+#[allow(clippy::too_many_arguments)]
+fn render_episode(
+    out: &mut String,
+    db: &Db,
+    generation: u64,
+    doc_id: u64,
+    parsed_query: &BoxedQuery,
+    is_phrase_query: bool,
+    query: &SearchRequest,
+    budget: &mut Budget,
+) -> ServerResult<()> {
+    let txn = db.store.begin_read()?;
+    let sentences_db = txn.open_table(db.store.terms_to_sentences)?;
+    let ep_db = db.store.get_docs_accessor(txn.get())?;
+    let mut ep_db = same_generation(ep_db, generation)?;
+    let mut doc_reader = ep_db.get_doc(doc_id)?;
+    let doc = doc_reader.try_read_doc()?;
 
-            for sentence_id in sentence_ids.value().ids.iter() {
-                if !seen_sentences.insert(sentence_id.value()) {
-                    continue;
-                }
+    write_episode(
+        out,
+        &sentences_db,
+        doc,
+        parsed_query,
+        is_phrase_query,
+        query,
+        budget,
+    )
+}
 
-                let sentence = &doc.tokens[sentence_id.value() as usize];
-                if let Some(highlighted) =
-                    sentence.highlight(&parsed_query.terms, &doc.text, is_phrase_query)
-                {
-                    highlighted.serialize_into(highlights.add_array());
-                }
+/// Passes `ep_db` through if it reads the store at `generation`. Documents from any other generation
+/// may be missing or have different sentences than the search results and highlight terms were made for.
+pub(crate) fn same_generation(
+    ep_db: SimpleDocsAccessor<'_>,
+    generation: u64,
+) -> ServerResult<SimpleDocsAccessor<'_>> {
+    if ep_db.generation() != generation {
+        return Err(ServerError::IndexUpdated);
+    }
+    Ok(ep_db)
+}
+
+/// Serializes one entry of the `episodes` array, highlights included if they were asked for.
+fn write_episode(
+    out: &mut String,
+    sentences_db: &impl ReadableTable<TermsToSentencesId, SentenceList<'static>>,
+    doc: &ArchivedStoredEpisode,
+    parsed_query: &BoxedQuery,
+    is_phrase_query: bool,
+    query: &SearchRequest,
+    budget: &mut Budget,
+) -> ServerResult<()> {
+    let mut ser = Serializer::new(out);
+    let mut episode = ser.object();
+    episode.field(noescape!("curiosity_id"), doc.id.value());
+    episode.field(noescape!("slug"), doc.slug.as_str());
+    episode.field(noescape!("title"), doc.title.as_str());
+    if let Some(docs_id) = doc.docs_id.as_ref() {
+        episode.field("docs_id", docs_id.as_str());
+    }
+
+    episode.field("season", noescape!(doc.season.as_ref()));
+
+    if !query.highlight {
+        episode.end();
+        return Ok(());
+    }
+
+    let mut highlights = episode.array_field(noescape!("highlights"));
+    let mut highlight_times: Vec<(Option<u32>, Option<u32>)> = Vec::new();
+
+    for_each_highlight(
+        sentences_db,
+        doc,
+        &parsed_query.terms,
+        is_phrase_query,
+        &query.exclude_speakers,
+        budget,
+        |sentence, mut highlighted| {
+            if query.snippet_length > 0 {
+                highlighted.trim(query.snippet_length);
             }
+
+            highlighted.serialize_into(highlights.add_array());
+            highlight_times.push(sentence.timestamps());
+        },
+    )?;
+
+    highlights.end();
+
+    let mut times = episode.array_field(noescape!("highlight_times"));
+    for (start, end) in highlight_times {
+        if start.is_none() && end.is_none() {
+            times.add(None::<u32>);
+            continue;
         }
 
-        highlights.end();
+        let mut time = times.add_object();
+        time.field(noescape!("start"), start);
+        time.field(noescape!("end"), end);
+    }
+    times.end();
 
-        episode.end();
+    episode.end();
+
+    Ok(())
+}
+
+/// Parses the query, rejecting it if it has more distinct terms than `limits` allow.
+pub(crate) fn build_query(
+    db: &Db,
+    query: &SearchRequest,
+    limits: &SearchLimits,
+) -> ServerResult<(BoxedQuery, bool)> {
+    let (parsed, is_phrase_query) = match query.kind {
+        QueryKind::Phrase if query.query.split_ascii_whitespace().take(2).count() >= 2 => {
+            (db.phrase_query(&query.query).boxed(), true)
+        }
+        QueryKind::Web => (db.parse_query(&query.query)?.boxed(), false),
+        _ => (db.keyword_query(&query.query).boxed(), false),
+    };
+
+    if parsed.term_count > limits.max_query_terms {
+        return Err(ServerError::TooManyQueryTerms {
+            count: parsed.term_count,
+            max: limits.max_query_terms,
+        });
     }
 
-    episodes.end();
+    Ok((parsed, is_phrase_query))
+}
 
-    response_obj.end();
+pub(crate) fn build_filters(db: &Db, query: &SearchRequest) -> ServerResult<SearchFilters> {
+    // episodes without a transcript aren't in the index, so there's nothing to place them by either
+    let after_episode = match query.after_episode.as_deref() {
+        Some(slug) => Some(
+            db.store
+                .episode_id(slug)?
+                .map(|id| db.episode_position(id))
+                .transpose()?
+                .flatten()
+                .ok_or_else(|| ServerError::UnknownEpisode(slug.to_owned()))?,
+        ),
+        None => None,
+    };
 
-    Ok(HttpResponseBuilder::new(StatusCode::OK)
-        .content_type("application/json")
-        .body(out))
+    Ok(SearchFilters {
+        seasons: query.seasons.clone(),
+        season_from: query.season_from,
+        season_to: query.season_to,
+        episode_from: query.episode_from,
+        episode_to: query.episode_to,
+        after_episode,
+        exclude_seasons: query.exclude_seasons.clone(),
+        exclude_speakers: if query.exclude_speaker_episodes {
+            query.exclude_speakers.clone()
+        } else {
+            SmallVec::new()
+        },
+    })
+}
+
+/// Highlights every sentence of `doc` that contains one of `terms`, each sentence at most once,
+/// except for lines said by one of `exclude_speakers`.
+/// Stops early, marking `budget` truncated, once it runs out.
+pub(crate) fn for_each_highlight<'d>(
+    sentences_db: &impl ReadableTable<TermsToSentencesId, SentenceList<'static>>,
+    doc: &'d ArchivedStoredEpisode,
+    terms: &[u32],
+    is_phrase_query: bool,
+    exclude_speakers: &[Friend],
+    budget: &mut Budget,
+    mut f: impl FnMut(&'d ArchivedSentence, HighlightedSentence<'d>),
+) -> ServerResult<()> {
+    let mut term_to_sentence_id = TermsToSentencesId::new(doc.id.value(), 0);
+    let mut seen_sentences: SetU32 = SetU32::new();
+
+    for term in terms.iter() {
+        term_to_sentence_id.set_term(*term);
+        let Some(sentence_ids) = sentences_db.get(&term_to_sentence_id)? else {
+            continue;
+        };
+
+        for sentence_id in sentence_ids.value().ids.iter() {
+            if !seen_sentences.insert(sentence_id.value()) {
+                continue;
+            }
+
+            if !budget.scan() {
+                return Ok(());
+            }
+
+            let sentence = &doc.tokens[sentence_id.value() as usize];
+            if said_by(sentence, exclude_speakers) {
+                continue;
+            }
+
+            if let Some(highlighted) = sentence.highlight(terms, &doc.text, is_phrase_query) {
+                if !budget.highlight() {
+                    return Ok(());
+                }
+
+                f(sentence, highlighted);
+            }
+        }
+    }
+
+    Ok(())
 }
@@ -0,0 +1,47 @@
+use std::convert::Infallible;
+
+use actix_web::web::Bytes;
+use futures_util::Stream;
+use tokio::sync::mpsc;
+
+use crate::{ServerError, ServerResult};
+
+/// How many chunks can be waiting on a slow client before the producer blocks.
+const CHUNKS_IN_FLIGHT: usize = 16;
+
+pub(crate) struct ChunkSink {
+    tx: mpsc::Sender<Bytes>,
+}
+
+impl ChunkSink {
+    /// Fails with [`ServerError::StreamClosed`] once the client has gone away, so producers can stop early.
+    pub fn send(&self, chunk: impl Into<Bytes>) -> ServerResult<()> {
+        self.tx
+            .blocking_send(chunk.into())
+            .map_err(|_| ServerError::StreamClosed)
+    }
+}
+
+/// Runs `produce` on the blocking pool, streaming every chunk it sends as the response body.
+/// Errors can't change the status once the body has started, so they end the stream early instead.
+pub(crate) fn stream_blocking<F>(produce: F) -> impl Stream<Item = Result<Bytes, Infallible>>
+where
+    F: FnOnce(&ChunkSink) -> ServerResult<()> + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(CHUNKS_IN_FLIGHT);
+    let span = tracing::Span::current();
+
+    actix_web::rt::task::spawn_blocking(move || {
+        let _entered = span.enter();
+        match produce(&ChunkSink { tx }) {
+            Ok(()) => {}
+            Err(ServerError::StreamClosed) => tracing::debug!("client went away mid-response"),
+            Err(e) => tracing::error!(error = %e, "error while streaming response"),
+        }
+    });
+
+    futures_util::stream::unfold(rx, |mut rx| async move {
+        let chunk = rx.recv().await?;
+        Some((Ok(chunk), rx))
+    })
+}
@@ -1,4 +1,4 @@
-use curiosity::SeasonId;
+use curiosity::{Friend, SeasonId};
 
 use curiosity::serialization_crimes::*;
 
@@ -7,7 +7,7 @@ use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
 use smartstring::{Compact, SmartString};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct SearchRequest {
     #[serde(alias = "q", default)]
     pub query: SmartString<Compact>,
@@ -15,17 +15,40 @@ pub struct SearchRequest {
     pub kind: QueryKind,
     #[serde(default, deserialize_with = "deserialize_stringified_list")]
     pub seasons: SmallVec<[SeasonId; 16]>,
+    /// Inclusive bounds on the season, in chronological order.
     #[serde(default)]
-    pub highlight: bool,
+    pub season_from: Option<SeasonId>,
+    #[serde(default)]
+    pub season_to: Option<SeasonId>,
+    /// Inclusive bounds on an episode's number within its season.
+    #[serde(default)]
+    pub episode_from: Option<u64>,
     #[serde(default)]
-    pub _curiosity_internal_offset: usize,
+    pub episode_to: Option<u64>,
+    /// Slug of an episode; only episodes after it in season, then sorting number order are returned.
+    #[serde(default)]
+    pub after_episode: Option<SmartString<Compact>>,
+    #[serde(default, deserialize_with = "deserialize_stringified_list")]
+    pub exclude_seasons: SmallVec<[SeasonId; 16]>,
+    /// Hides the lines these speakers said from highlights and exports,
+    /// and leaves out episodes where those lines were the only matches.
+    #[serde(default, deserialize_with = "deserialize_stringified_list")]
+    pub exclude_speakers: SmallVec<[Friend; 16]>,
+    /// Also leaves out every episode one of `exclude_speakers` has a line in.
+    #[serde(default)]
+    pub exclude_speaker_episodes: bool,
+    #[serde(default)]
+    pub highlight: bool,
     #[serde(default)]
     pub page: Option<String>,
     #[serde(default = "page_size_default")]
     pub page_size: usize,
+    /// Highlights longer than this many bytes get trimmed around their matches; 0 turns trimming off.
+    #[serde(default = "snippet_length_default")]
+    pub snippet_length: usize,
 }
 
-#[derive(Serialize, Deserialize, Default, Debug)]
+#[derive(Serialize, Deserialize, Default, Debug, Clone, Copy)]
 #[serde(rename_all = "kebab-case")]
 pub enum QueryKind {
     #[default]
@@ -33,3 +56,13 @@ pub enum QueryKind {
     Phrase,
     Web,
 }
+
+impl QueryKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            QueryKind::Keywords => "keywords",
+            QueryKind::Phrase => "phrase",
+            QueryKind::Web => "web",
+        }
+    }
+}
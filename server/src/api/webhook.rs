@@ -0,0 +1,74 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::config::Config;
+use crate::update::{Updater, MIRROR_REF};
+use crate::{ServerError, ServerResult};
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(serde::Deserialize)]
+struct PushEvent {
+    #[serde(rename = "ref")]
+    git_ref: String,
+}
+
+#[derive(serde::Serialize)]
+struct WebhookResponse {
+    queued: bool,
+}
+
+/// GitHub webhook for the transcript mirror. Pushes to the mirrored branch queue an update,
+/// which starts once pushes have stopped coming in for `CURIOSITY_WEBHOOK_DEBOUNCE_SECS`.
+#[actix_web::post("/webhooks/github")]
+pub async fn github_webhook(
+    req: HttpRequest,
+    body: web::Bytes,
+    config: web::Data<Config>,
+    updater: web::Data<Updater>,
+) -> ServerResult<HttpResponse> {
+    let secret = config
+        .webhook_secret
+        .as_deref()
+        .ok_or(ServerError::WebhookDisabled)?;
+
+    let signature = req
+        .headers()
+        .get("x-hub-signature-256")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("sha256="))
+        .and_then(decode_hex)
+        .ok_or(ServerError::BadWebhookSignature)?;
+
+    let mut mac = HmacSha256::new_from_slice(secret).expect("hmac takes keys of any length");
+    mac.update(&body);
+    mac.verify_slice(&signature)
+        .map_err(|_| ServerError::BadWebhookSignature)?;
+
+    let event = req
+        .headers()
+        .get("x-github-event")
+        .and_then(|value| value.to_str().ok());
+
+    // anything else, pings included, is acknowledged and ignored
+    let queued =
+        event == Some("push") && serde_json::from_slice::<PushEvent>(&body)?.git_ref == MIRROR_REF;
+    if queued {
+        Updater::queue(updater, config.webhook_debounce);
+    }
+
+    Ok(HttpResponse::Accepted().json(WebhookResponse { queued }))
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair)
+                .ok()
+                .filter(|pair| pair.len() == 2)?;
+            u8::from_str_radix(pair, 16).ok()
+        })
+        .collect()
+}
@@ -0,0 +1,173 @@
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use rand::RngCore;
+
+/// Server settings read from the environment at startup.
+#[derive(Clone)]
+pub struct Config {
+    /// Key page tokens are signed with: `CURIOSITY_PAGE_SECRET` if it's set, otherwise a random one.
+    /// [`Config::load_page_secret`] keeps the random one in the data folder, so tokens outlive restarts.
+    pub page_secret: Vec<u8>,
+    pub limits: SearchLimits,
+    /// Bounds on `/search/export`, which walks every result rather than one page (`CURIOSITY_EXPORT_*`).
+    /// The query term limit is shared with `limits`.
+    pub export_limits: SearchLimits,
+    /// File listing words to leave out of keyword highlights, one per line (`CURIOSITY_STOP_WORDS`).
+    pub stop_words: Option<PathBuf>,
+    /// How many `/search` responses to keep around (`CURIOSITY_SEARCH_CACHE_SIZE`); 0 turns the cache off.
+    pub search_cache_size: usize,
+    /// Bearer token for every endpoint under `/api/admin` (`CURIOSITY_ADMIN_TOKEN`); they're disabled without one.
+    pub admin_token: Option<String>,
+    /// Secret GitHub signs push webhooks with (`CURIOSITY_WEBHOOK_SECRET`); the webhook endpoint is disabled without one.
+    pub webhook_secret: Option<Vec<u8>>,
+    /// How long after the last push to wait before updating (`CURIOSITY_WEBHOOK_DEBOUNCE_SECS`).
+    pub webhook_debounce: Duration,
+    /// Where `POST /api/admin/backups` writes snapshot archives (`CURIOSITY_BACKUP_DIR`).
+    pub backup_dir: PathBuf,
+}
+
+/// Bounds on how much work a single search can do.
+#[derive(Clone, Debug)]
+pub struct SearchLimits {
+    /// Queries with more distinct terms than this are rejected outright.
+    pub max_query_terms: usize,
+    /// Highlights returned per response; the rest are dropped and the response is marked truncated.
+    pub max_highlights: usize,
+    /// Sentences looked at while highlighting a response, matching or not.
+    pub max_sentences_scanned: usize,
+    /// Searches that take longer than this to find their episodes fail, and highlighting stops once it's up.
+    pub timeout: Duration,
+}
+
+impl Default for SearchLimits {
+    fn default() -> SearchLimits {
+        SearchLimits {
+            max_query_terms: 32,
+            max_highlights: 2_000,
+            max_sentences_scanned: 50_000,
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+impl SearchLimits {
+    /// Much looser than a page of search results, since an export is meant to be complete.
+    pub fn export_defaults() -> SearchLimits {
+        SearchLimits {
+            max_highlights: 200_000,
+            max_sentences_scanned: 2_000_000,
+            timeout: Duration::from_secs(60),
+            ..SearchLimits::default()
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Config {
+        let page_secret = env_page_secret().unwrap_or_else(random_page_secret);
+
+        let defaults = SearchLimits::default();
+        let limits = SearchLimits {
+            max_query_terms: env_or("CURIOSITY_MAX_QUERY_TERMS", defaults.max_query_terms),
+            max_highlights: env_or("CURIOSITY_MAX_HIGHLIGHTS", defaults.max_highlights),
+            max_sentences_scanned: env_or(
+                "CURIOSITY_MAX_SENTENCES_SCANNED",
+                defaults.max_sentences_scanned,
+            ),
+            timeout: Duration::from_millis(env_or(
+                "CURIOSITY_SEARCH_TIMEOUT_MS",
+                defaults.timeout.as_millis() as u64,
+            )),
+        };
+
+        let defaults = SearchLimits::export_defaults();
+        let export_limits = SearchLimits {
+            max_query_terms: limits.max_query_terms,
+            max_highlights: env_or("CURIOSITY_EXPORT_MAX_HIGHLIGHTS", defaults.max_highlights),
+            max_sentences_scanned: env_or(
+                "CURIOSITY_EXPORT_MAX_SENTENCES_SCANNED",
+                defaults.max_sentences_scanned,
+            ),
+            timeout: Duration::from_millis(env_or(
+                "CURIOSITY_EXPORT_TIMEOUT_MS",
+                defaults.timeout.as_millis() as u64,
+            )),
+        };
+
+        Config {
+            page_secret,
+            limits,
+            export_limits,
+            stop_words: std::env::var_os("CURIOSITY_STOP_WORDS").map(PathBuf::from),
+            search_cache_size: env_or("CURIOSITY_SEARCH_CACHE_SIZE", 256),
+            admin_token: std::env::var("CURIOSITY_ADMIN_TOKEN")
+                .ok()
+                .filter(|token| !token.is_empty()),
+            webhook_secret: std::env::var("CURIOSITY_WEBHOOK_SECRET")
+                .ok()
+                .filter(|secret| !secret.is_empty())
+                .map(String::into_bytes),
+            webhook_debounce: Duration::from_secs(env_or("CURIOSITY_WEBHOOK_DEBOUNCE_SECS", 60)),
+            backup_dir: std::env::var_os("CURIOSITY_BACKUP_DIR")
+                .map_or_else(|| PathBuf::from("./backups"), PathBuf::from),
+        }
+    }
+
+    /// Unless `CURIOSITY_PAGE_SECRET` is set, uses the page secret stored in `folder`, generating it the first time.
+    pub fn load_page_secret(&mut self, folder: &Path) -> std::io::Result<()> {
+        if env_page_secret().is_some() {
+            return Ok(());
+        }
+
+        let path = folder.join(PAGE_SECRET_FILE);
+        self.page_secret = match std::fs::read(&path) {
+            Ok(secret) if !secret.is_empty() => secret,
+            Ok(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("{} is empty", path.display()),
+                ))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let secret = random_page_secret();
+                std::fs::write(&path, &secret)?;
+                tracing::info!(path = %path.display(), "generated page secret");
+                secret
+            }
+            Err(e) => return Err(e),
+        };
+
+        Ok(())
+    }
+}
+
+/// Where [`Config::load_page_secret`] keeps a generated page secret, relative to the data folder.
+const PAGE_SECRET_FILE: &str = "page_secret";
+
+fn env_page_secret() -> Option<Vec<u8>> {
+    std::env::var("CURIOSITY_PAGE_SECRET")
+        .ok()
+        .filter(|secret| !secret.is_empty())
+        .map(String::into_bytes)
+}
+
+fn random_page_secret() -> Vec<u8> {
+    let mut secret = vec![0; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+    secret
+}
+
+/// Parses `name` from the environment, falling back to `default` if it's unset or invalid.
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    match std::env::var(name) {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            tracing::warn!(name, value, "ignoring invalid setting");
+            default
+        }),
+        Err(_) => default,
+    }
+}
@@ -0,0 +1,40 @@
+use actix_web::{web, HttpResponse};
+
+use curiosity::db::Db;
+
+use crate::update::{UpdateRun, Updater};
+use crate::ServerResult;
+
+/// Liveness: answers as long as the server is accepting requests.
+#[actix_web::get("/healthz")]
+pub async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "ok": true }))
+}
+
+#[derive(serde::Serialize)]
+struct Readiness {
+    ready: bool,
+    generation: u64,
+    docs: u64,
+    last_update: Option<UpdateRun>,
+}
+
+/// Readiness: 200 once there's an index to search, 503 while the server is still waiting on its first update.
+#[actix_web::get("/readyz")]
+pub async fn readyz(db: web::Data<Db>, updater: web::Data<Updater>) -> ServerResult<HttpResponse> {
+    let docs = db.num_docs();
+    let readiness = Readiness {
+        ready: docs > 0,
+        generation: db.store.generation()?,
+        docs,
+        last_update: updater.latest(),
+    };
+
+    let mut response = if readiness.ready {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::ServiceUnavailable()
+    };
+
+    Ok(response.json(readiness))
+}
@@ -4,6 +4,10 @@ use reqwest::StatusCode;
 use thiserror::Error;
 
 pub mod api;
+pub mod config;
+pub mod health;
+pub mod logging;
+pub mod metrics;
 pub mod update;
 
 #[derive(Debug, Error)]
@@ -21,9 +25,91 @@ pub enum ServerError {
     #[error(transparent)]
     ZipError(#[from] zip::result::ZipError),
     #[error(transparent)]
+    CsvError(#[from] csv::Error),
+    #[error(transparent)]
     REDBError(#[from] redb::Error),
+    #[error(transparent)]
+    MetricsError(#[from] prometheus::Error),
     #[error("invalid page token")]
     BadPageToken,
+    #[error("page token has expired, start the search over")]
+    ExpiredPageToken,
+    #[error("page token format version {0} isn't supported, start the search over")]
+    UnsupportedPageToken(u8),
+    #[error("the index was updated since this page token was issued, start the search over")]
+    StalePageToken,
+    #[error("the index was updated while results were being read, search again")]
+    IndexUpdated,
+    #[error("query has {count} terms, at most {max} are allowed")]
+    TooManyQueryTerms { count: usize, max: usize },
+    #[error("search took longer than {0} ms")]
+    SearchTimeout(u128),
+    #[error("no episode with slug {0}")]
+    UnknownEpisode(String),
+    #[error("client closed the response stream")]
+    StreamClosed,
+    #[error("missing or wrong admin token")]
+    Unauthorized,
+    #[error("admin endpoints are disabled, set CURIOSITY_ADMIN_TOKEN to enable them")]
+    AdminDisabled,
+    #[error("webhooks are disabled, set CURIOSITY_WEBHOOK_SECRET to enable them")]
+    WebhookDisabled,
+    #[error("missing or invalid webhook signature")]
+    BadWebhookSignature,
+    #[error("an update is already running")]
+    UpdateInProgress,
+    #[error("no update is running")]
+    NoUpdateRunning,
+}
+
+impl ServerError {
+    /// Names the variant for `curiosity_errors_total`, looking inside library errors so that
+    /// tantivy, redb, IO and the rest don't all end up counted as the same kind.
+    pub fn metric_label(&self) -> &'static str {
+        use ServerError::*;
+
+        match self {
+            CuriosityLibError(e) => {
+                use CuriosityError::*;
+                match e {
+                    QueryParserError(_) => "query_parser",
+                    Tantivy(_) => "tantivy",
+                    TantivyOpenError(_) => "tantivy_open",
+                    IOError(_) => "io",
+                    REDBError(_) => "redb",
+                    PostcardError(_) => "postcard",
+                    ZipError(_) => "zip",
+                    InvalidSnapshot(_) => "invalid_snapshot",
+                    NotFound => "not_found",
+                    InvalidDocument(..) => "invalid_document",
+                    Cancelled => "cancelled",
+                }
+            }
+            IOError(_) => "io",
+            SerdeJsonError(_) => "serde_json",
+            PostcardError(_) => "postcard",
+            ReqwestError(_) => "reqwest",
+            ZipError(_) => "zip",
+            CsvError(_) => "csv",
+            REDBError(_) => "redb",
+            MetricsError(_) => "metrics",
+            BadPageToken => "bad_page_token",
+            ExpiredPageToken => "expired_page_token",
+            UnsupportedPageToken(_) => "unsupported_page_token",
+            StalePageToken => "stale_page_token",
+            IndexUpdated => "index_updated",
+            TooManyQueryTerms { .. } => "too_many_query_terms",
+            SearchTimeout(_) => "search_timeout",
+            UnknownEpisode(_) => "unknown_episode",
+            StreamClosed => "stream_closed",
+            Unauthorized => "unauthorized",
+            AdminDisabled => "admin_disabled",
+            WebhookDisabled => "webhook_disabled",
+            BadWebhookSignature => "bad_webhook_signature",
+            UpdateInProgress => "update_in_progress",
+            NoUpdateRunning => "no_update_running",
+        }
+    }
 }
 
 impl ResponseError for ServerError {
@@ -44,7 +130,11 @@ impl ResponseError for ServerError {
                     IOError(e) => ("internal", e.to_string()),
                     REDBError(e) => ("internal", e.to_string()),
                     PostcardError(e) => ("internal", e.to_string()),
+                    ZipError(e) => ("internal", e.to_string()),
+                    InvalidSnapshot(e) => ("internal", e.clone()),
                     NotFound => ("internal", "document not found".to_string()),
+                    InvalidDocument(..) => ("internal", e.to_string()),
+                    Cancelled => ("update", "update cancelled".to_string()),
                 }
             }
             REDBError(e) => ("internal", e.to_string()),
@@ -53,12 +143,78 @@ impl ResponseError for ServerError {
             PostcardError(e) => ("internal", e.to_string()),
             ReqwestError(e) => ("internal", e.to_string()),
             ZipError(e) => ("internal", e.to_string()),
+            CsvError(e) => ("internal", e.to_string()),
+            MetricsError(e) => ("internal", e.to_string()),
             BadPageToken => {
                 status = StatusCode::BAD_REQUEST;
                 ("page", BadPageToken.to_string())
             }
+            ExpiredPageToken => {
+                status = StatusCode::BAD_REQUEST;
+                ("page", ExpiredPageToken.to_string())
+            }
+            UnsupportedPageToken(version) => {
+                status = StatusCode::BAD_REQUEST;
+                ("page", UnsupportedPageToken(*version).to_string())
+            }
+            StalePageToken => {
+                status = StatusCode::CONFLICT;
+                ("page", StalePageToken.to_string())
+            }
+            IndexUpdated => {
+                status = StatusCode::CONFLICT;
+                ("update", IndexUpdated.to_string())
+            }
+            TooManyQueryTerms { count, max } => {
+                status = StatusCode::BAD_REQUEST;
+                (
+                    "limit",
+                    TooManyQueryTerms {
+                        count: *count,
+                        max: *max,
+                    }
+                    .to_string(),
+                )
+            }
+            SearchTimeout(millis) => {
+                status = StatusCode::SERVICE_UNAVAILABLE;
+                ("limit", SearchTimeout(*millis).to_string())
+            }
+            UnknownEpisode(slug) => {
+                status = StatusCode::NOT_FOUND;
+                ("episode", UnknownEpisode(slug.clone()).to_string())
+            }
+            StreamClosed => ("internal", StreamClosed.to_string()),
+            Unauthorized => {
+                status = StatusCode::UNAUTHORIZED;
+                ("auth", Unauthorized.to_string())
+            }
+            AdminDisabled => {
+                status = StatusCode::FORBIDDEN;
+                ("auth", AdminDisabled.to_string())
+            }
+            WebhookDisabled => {
+                status = StatusCode::FORBIDDEN;
+                ("auth", WebhookDisabled.to_string())
+            }
+            BadWebhookSignature => {
+                status = StatusCode::UNAUTHORIZED;
+                ("auth", BadWebhookSignature.to_string())
+            }
+            UpdateInProgress => {
+                status = StatusCode::CONFLICT;
+                ("update", UpdateInProgress.to_string())
+            }
+            NoUpdateRunning => {
+                status = StatusCode::CONFLICT;
+                ("update", NoUpdateRunning.to_string())
+            }
         };
 
+        crate::metrics::ERRORS
+            .with_label_values(&[self.metric_label()])
+            .inc();
+
         #[derive(serde::Serialize)]
         struct ErrResponse<'a> {
             err: bool,
@@ -0,0 +1,72 @@
+use std::{fmt, time::Instant};
+
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue},
+    HttpMessage,
+};
+use futures_util::Future;
+use rand::Rng;
+use tracing::Instrument;
+use tracing_subscriber::EnvFilter;
+
+/// Sets up the global subscriber. Levels are filtered with `RUST_LOG` (default `info`),
+/// and `CURIOSITY_LOG_FORMAT=json` switches to one JSON object per line.
+pub fn init() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    match std::env::var("CURIOSITY_LOG_FORMAT").as_deref() {
+        Ok("json") => builder.json().flatten_event(true).init(),
+        _ => builder.init(),
+    }
+}
+
+/// Random id attached to every request's logs and echoed back in the `x-request-id` header.
+#[derive(Clone, Copy, Debug)]
+pub struct RequestId(u64);
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// Middleware, for `App::wrap_fn`, that runs each request in a span carrying its [`RequestId`] and logs how it went.
+pub fn request_span<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let request_id = RequestId(rand::thread_rng().gen());
+    let span = tracing::info_span!(
+        "request",
+        request_id = %request_id,
+        method = %req.method(),
+        path = %req.path(),
+    );
+
+    req.extensions_mut().insert(request_id);
+    let started = Instant::now();
+    let response = span.in_scope(|| srv.call(req));
+
+    async move {
+        let mut response = response.await?;
+        tracing::info!(
+            status = response.status().as_u16(),
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "request finished"
+        );
+
+        if let Ok(value) = HeaderValue::from_str(&request_id.to_string()) {
+            response
+                .headers_mut()
+                .insert(HeaderName::from_static("x-request-id"), value);
+        }
+
+        Ok(response)
+    }
+    .instrument(span)
+}
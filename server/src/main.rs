@@ -3,29 +3,101 @@
 use std::sync::Arc;
 
 use std::error::Error;
+use std::fs::File;
+use std::path::Path;
 
 use actix_cors::Cors;
 use actix_web::{web, App, HttpServer};
 
 use curiosity::db::Db;
+use curiosity::stop_words::StopWords;
+use server::api::cache::SearchCache;
+use server::config::Config;
+use server::update::Updater;
+
+const DATA_DIR: &str = "./satt";
 
 #[actix_web::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    std::fs::create_dir_all("./satt");
-    let mut db: Db = Db::new("./satt")?;
-    server::update::update_database(db.clone()).await.unwrap();
+    server::logging::init();
+
+    // `server backup <archive>` and `server restore <archive>` work on the data folder while the server is stopped
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        Some("backup") => {
+            let path = args.next().ok_or("usage: server backup <archive>")?;
+            let manifest = match curiosity::snapshot::backup(DATA_DIR, File::create(&path)?) {
+                Ok(manifest) => manifest,
+                Err(e) => {
+                    std::fs::remove_file(&path);
+                    return Err(e.into());
+                }
+            };
+            tracing::info!(path, generation = manifest.generation, "wrote backup");
+            return Ok(());
+        }
+        Some("restore") => {
+            let path = args.next().ok_or("usage: server restore <archive>")?;
+            let manifest = curiosity::snapshot::restore(File::open(&path)?, DATA_DIR)?;
+            tracing::info!(path, generation = manifest.generation, "restored backup");
+            return Ok(());
+        }
+        Some(command) => return Err(format!("unknown command {command}").into()),
+        None => {}
+    }
+
+    let mut config = Config::from_env();
 
+    std::fs::create_dir_all(DATA_DIR);
+    config.load_page_secret(Path::new(DATA_DIR))?;
+    let mut db: Db = Db::new(DATA_DIR)?;
+    if let Some(path) = config.stop_words.as_ref() {
+        let stop_words = StopWords::from_file(path)?;
+        tracing::info!(
+            count = stop_words.len(),
+            path = %path.display(),
+            "loaded stop words"
+        );
+        db = db.with_stop_words(stop_words);
+    }
+
+    // before anything else holds on to the store
     Arc::get_mut(&mut db.store.db).unwrap().compact();
 
-    let db_for_update = db.clone();
-    actix_web::rt::spawn(server::update::update_database_periodically(db_for_update));
+    let cache = web::Data::new(SearchCache::new(config.search_cache_size));
+    let updater = web::Data::new(Updater::new(db.clone(), cache.clone()));
+
+    // serve whatever index is already on disk while the first update runs, /readyz says when there's something to search
+    actix_web::rt::spawn(server::update::update_database_periodically(
+        updater.clone(),
+    ));
 
     HttpServer::new(move || {
         App::new()
             .wrap(Cors::permissive())
-            .service(web::scope("/api").service(server::api::search))
+            .wrap_fn(server::logging::request_span)
+            .service(
+                web::scope("/api")
+                    .service(server::api::search)
+                    .service(server::api::export)
+                    .service(server::api::episode)
+                    .service(server::api::ingestion_report)
+                    .service(server::api::search_cache_stats)
+                    .service(server::api::trigger_update)
+                    .service(server::api::update_history)
+                    .service(server::api::current_update)
+                    .service(server::api::cancel_update)
+                    .service(server::api::create_backup)
+                    .service(server::api::github_webhook),
+            )
+            .service(server::metrics::metrics)
+            .service(server::health::healthz)
+            .service(server::health::readyz)
             .service(actix_files::Files::new("/", "./static").index_file("index.html"))
             .app_data(web::Data::new(db.clone()))
+            .app_data(web::Data::new(config.clone()))
+            .app_data(cache.clone())
+            .app_data(updater.clone())
     })
     .bind(("0.0.0.0", 8080))?
     .run()
@@ -0,0 +1,135 @@
+use actix_web::{web, HttpResponse};
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_gauge, register_histogram, register_histogram_vec, register_int_counter_vec,
+    register_int_gauge_vec, Encoder, Gauge, Histogram, HistogramVec, IntCounterVec, IntGaugeVec,
+    TextEncoder,
+};
+
+use curiosity::db::Db;
+
+use crate::api::cache::SearchCache;
+use crate::ServerResult;
+
+pub static SEARCH_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "curiosity_search_duration_seconds",
+        "Time until a /search response starts streaming, by query kind",
+        &["kind"]
+    )
+    .unwrap()
+});
+
+pub static INDEX_SEARCH_DURATION: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "curiosity_index_search_duration_seconds",
+        "Time spent in Db::search"
+    )
+    .unwrap()
+});
+
+pub static SEARCH_RESULTS: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "curiosity_search_results",
+        "Episodes returned per /search page",
+        vec![0.0, 1.0, 5.0, 10.0, 25.0, 50.0, 100.0]
+    )
+    .unwrap()
+});
+
+pub static ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "curiosity_errors_total",
+        "Error responses, by which ServerError (or CuriosityError inside it) caused them",
+        &["kind"]
+    )
+    .unwrap()
+});
+
+pub static UPDATE_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "curiosity_update_duration_seconds",
+        "Duration of database updates, by outcome",
+        &["outcome"],
+        vec![1.0, 5.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0]
+    )
+    .unwrap()
+});
+
+pub static LAST_SUCCESSFUL_UPDATE: Lazy<Gauge> = Lazy::new(|| {
+    register_gauge!(
+        "curiosity_last_successful_update_timestamp_seconds",
+        "Unix time the last database update finished successfully"
+    )
+    .unwrap()
+});
+
+pub static INGESTED_EPISODES: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "curiosity_ingested_episodes",
+        "Episodes in the last ingestion report, by outcome",
+        &["outcome"]
+    )
+    .unwrap()
+});
+
+pub static INGESTION_DURATION: Lazy<Gauge> = Lazy::new(|| {
+    register_gauge!(
+        "curiosity_ingestion_duration_seconds",
+        "Time the last add_documents run took"
+    )
+    .unwrap()
+});
+
+static STORAGE_BYTES: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "curiosity_storage_bytes",
+        "Size on disk, by component",
+        &["component"]
+    )
+    .unwrap()
+});
+
+pub static SEARCH_CACHE: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "curiosity_search_cache_lookups_total",
+        "Search cache lookups, by result",
+        &["result"]
+    )
+    .unwrap()
+});
+
+static SEARCH_CACHE_ENTRIES: Lazy<Gauge> = Lazy::new(|| {
+    register_gauge!(
+        "curiosity_search_cache_entries",
+        "Responses currently in the search cache"
+    )
+    .unwrap()
+});
+
+/// Metrics in the Prometheus text format. Sizes and cache counters are sampled on every scrape.
+#[actix_web::get("/metrics")]
+pub async fn metrics(
+    db: web::Data<Db>,
+    cache: web::Data<SearchCache>,
+) -> ServerResult<HttpResponse> {
+    let usage = db.disk_usage()?;
+    STORAGE_BYTES
+        .with_label_values(&["store"])
+        .set(usage.store as i64);
+    STORAGE_BYTES
+        .with_label_values(&["index"])
+        .set(usage.index as i64);
+    STORAGE_BYTES
+        .with_label_values(&["terms"])
+        .set(usage.terms as i64);
+
+    SEARCH_CACHE_ENTRIES.set(cache.stats().entries as f64);
+
+    let mut out = Vec::new();
+    TextEncoder::new().encode(&prometheus::gather(), &mut out)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(out))
+}
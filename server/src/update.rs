@@ -1,50 +1,529 @@
+use std::collections::VecDeque;
 use std::io::{Cursor, Read};
 
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::{collections::BTreeMap, path::Path};
 
-use curiosity::{db::Db, CuriosityError, Season, SeasonId};
+use actix_web::web;
+use curiosity::{
+    db::Db, report::unix_millis, store::SourceInfo, transcript::PARSER_VERSION, CuriosityError,
+    Season, SeasonId,
+};
+use parking_lot::Mutex;
+use redb::ReadableTable;
+use reqwest::header::{self, HeaderValue};
+use reqwest::StatusCode;
+use sha2::{Digest, Sha256};
+use tracing::Instrument;
 
-use crate::ServerResult;
+use crate::api::cache::SearchCache;
+use crate::{metrics, ServerError, ServerResult};
 
-pub async fn update_database_periodically(db: Db) -> ServerResult<()> {
+/// Runs past, the current one included, that [`Updater`] remembers, in memory and in the store.
+const HISTORY_LEN: usize = 50;
+
+pub const MIRROR_URL: &str =
+    "https://github.com/emily-signet/transcripts-at-the-table-mirror/archive/refs/heads/data.zip";
+/// The branch [`MIRROR_URL`] points at; pushes to any other branch don't change the archive.
+pub const MIRROR_REF: &str = "refs/heads/data";
+
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UpdateTrigger {
+    Scheduled,
+    Manual,
+    Webhook,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UpdateOutcome {
+    Succeeded,
+    Failed,
+    Cancelled,
+    /// The archive was the same one the index was last built from, so nothing was rebuilt.
+    Unchanged,
+}
+
+impl UpdateOutcome {
+    fn label(self) -> &'static str {
+        match self {
+            UpdateOutcome::Succeeded => "success",
+            UpdateOutcome::Failed => "failure",
+            UpdateOutcome::Cancelled => "cancelled",
+            UpdateOutcome::Unchanged => "unchanged",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UpdatePhase {
+    #[default]
+    Downloading,
+    Indexing,
+    Done,
+}
+
+/// How far along a run is. `episode` counts the episodes read so far out of the `episodes` that have a transcript.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct UpdateProgress {
+    pub phase: UpdatePhase,
+    pub episode: usize,
+    pub episodes: usize,
+    pub current_title: Option<String>,
+}
+
+/// One database update, finished or not. Timestamps are unix milliseconds.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct UpdateRun {
+    pub id: u64,
+    pub trigger: UpdateTrigger,
+    pub started_at: u64,
+    pub finished_at: Option<u64>,
+    pub duration_ms: Option<u64>,
+    pub outcome: Option<UpdateOutcome>,
+    pub error: Option<String>,
+    pub progress: UpdateProgress,
+}
+
+#[derive(Default)]
+struct UpdaterState {
+    next_id: u64,
+    /// newest first; while a run is going on, it's at the front
+    runs: VecDeque<UpdateRun>,
+    cancel: Option<Arc<AtomicBool>>,
+    /// when the queued webhook update should start, if there is one
+    queued_until: Option<Instant>,
+}
+
+/// Rebuilds the database from the transcript mirror, one run at a time, and keeps track of how each run went.
+pub struct Updater {
+    db: Db,
+    cache: web::Data<SearchCache>,
+    state: Arc<Mutex<UpdaterState>>,
+}
+
+impl Updater {
+    /// Picks up the history of earlier runs from the store.
+    pub fn new(db: Db, cache: web::Data<SearchCache>) -> Updater {
+        let runs = load_runs(&db).unwrap_or_else(|e| {
+            tracing::warn!(error = %e, "couldn't load the update history");
+            VecDeque::new()
+        });
+
+        Updater {
+            state: Arc::new(Mutex::new(UpdaterState {
+                next_id: runs.front().map_or(0, |run| run.id + 1),
+                runs,
+                ..UpdaterState::default()
+            })),
+            db,
+            cache,
+        }
+    }
+
+    /// The run in progress, if there is one.
+    pub fn current(&self) -> Option<UpdateRun> {
+        let state = self.state.lock();
+        state
+            .cancel
+            .as_ref()
+            .and_then(|_| state.runs.front().cloned())
+    }
+
+    /// The most recent run, whether it's still going or not.
+    pub fn latest(&self) -> Option<UpdateRun> {
+        self.state.lock().runs.front().cloned()
+    }
+
+    /// Past runs, newest first, including the one in progress.
+    pub fn history(&self) -> Vec<UpdateRun> {
+        self.state.lock().runs.iter().cloned().collect()
+    }
+
+    /// Asks the run in progress to stop. It finishes as cancelled once it notices, leaving the previous index in place.
+    pub fn cancel(&self) -> ServerResult<u64> {
+        let state = self.state.lock();
+        match (&state.cancel, state.runs.front()) {
+            (Some(cancel), Some(run)) => {
+                cancel.store(true, Ordering::Relaxed);
+                Ok(run.id)
+            }
+            _ => Err(ServerError::NoUpdateRunning),
+        }
+    }
+
+    /// Starts a run in the background and returns its id, unless one is already running.
+    pub fn spawn(updater: web::Data<Updater>, trigger: UpdateTrigger) -> ServerResult<u64> {
+        let (id, cancel) = updater.begin(trigger)?;
+        actix_web::rt::spawn(async move { updater.run(id, trigger, cancel).await });
+        Ok(id)
+    }
+
+    /// Starts a webhook update once `delay` has passed without another call, so a burst of pushes becomes one run.
+    /// If an update is running by then, waits another `delay` and tries again.
+    pub fn queue(updater: web::Data<Updater>, delay: Duration) {
+        let already_queued = updater
+            .state
+            .lock()
+            .queued_until
+            .replace(Instant::now() + delay)
+            .is_some();
+        if already_queued {
+            return;
+        }
+
+        actix_web::rt::spawn(async move {
+            loop {
+                let Some(deadline) = updater.state.lock().queued_until else {
+                    return;
+                };
+
+                let now = Instant::now();
+                if now < deadline {
+                    actix_web::rt::time::sleep(deadline - now).await;
+                    continue;
+                }
+
+                match updater.begin(UpdateTrigger::Webhook) {
+                    Ok((id, cancel)) => {
+                        // this run downloads the archive after every push queued so far
+                        updater.state.lock().queued_until = None;
+                        let _ = updater.run(id, UpdateTrigger::Webhook, cancel).await;
+                        return;
+                    }
+                    Err(_) => {
+                        updater.state.lock().queued_until = Some(Instant::now() + delay);
+                    }
+                }
+            }
+        });
+    }
+
+    /// Runs an update to completion, unless one is already running.
+    pub async fn update(&self, trigger: UpdateTrigger) -> ServerResult<()> {
+        let (id, cancel) = self.begin(trigger)?;
+        self.run(id, trigger, cancel).await
+    }
+
+    fn begin(&self, trigger: UpdateTrigger) -> ServerResult<(u64, Arc<AtomicBool>)> {
+        let mut state = self.state.lock();
+        if state.cancel.is_some() {
+            return Err(ServerError::UpdateInProgress);
+        }
+
+        let id = state.next_id;
+        state.next_id += 1;
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        state.cancel = Some(Arc::clone(&cancel));
+        let run = UpdateRun {
+            id,
+            trigger,
+            started_at: unix_millis(),
+            finished_at: None,
+            duration_ms: None,
+            outcome: None,
+            error: None,
+            progress: UpdateProgress::default(),
+        };
+        state.runs.push_front(run.clone());
+        state.runs.truncate(HISTORY_LEN);
+        drop(state);
+
+        self.save_run(&run);
+
+        Ok((id, cancel))
+    }
+
+    async fn run(
+        &self,
+        id: u64,
+        trigger: UpdateTrigger,
+        cancel: Arc<AtomicBool>,
+    ) -> ServerResult<()> {
+        let span = tracing::info_span!("update", id);
+        let started = Instant::now();
+        tracing::info!(parent: &span, "updating database");
+
+        // asking for an update by hand means rebuilding even if the archive is the same
+        let force = matches!(trigger, UpdateTrigger::Manual);
+        let result = rebuild(
+            self.db.clone(),
+            self.cache.clone(),
+            Arc::clone(&self.state),
+            force,
+            cancel,
+        )
+        .instrument(span.clone())
+        .await;
+
+        let outcome = match &result {
+            Ok(outcome) => *outcome,
+            Err(ServerError::CuriosityLibError(CuriosityError::Cancelled)) => {
+                UpdateOutcome::Cancelled
+            }
+            Err(_) => UpdateOutcome::Failed,
+        };
+
+        let elapsed = started.elapsed();
+        metrics::UPDATE_DURATION
+            .with_label_values(&[outcome.label()])
+            .observe(elapsed.as_secs_f64());
+
+        let elapsed_ms = elapsed.as_millis() as u64;
+        match &result {
+            Ok(UpdateOutcome::Unchanged) => {
+                metrics::LAST_SUCCESSFUL_UPDATE.set(unix_millis() as f64 / 1000.0);
+                tracing::info!(parent: &span, elapsed_ms, "archive unchanged, not rebuilding");
+            }
+            Ok(_) => {
+                metrics::LAST_SUCCESSFUL_UPDATE.set(unix_millis() as f64 / 1000.0);
+                tracing::info!(parent: &span, elapsed_ms, "database updated");
+            }
+            Err(_) if outcome == UpdateOutcome::Cancelled => {
+                tracing::info!(parent: &span, elapsed_ms, "database update cancelled")
+            }
+            Err(e) => {
+                tracing::error!(parent: &span, error = %e, elapsed_ms, "database update failed")
+            }
+        }
+
+        let mut state = self.state.lock();
+        state.cancel = None;
+        let finished = state.runs.iter_mut().find(|run| run.id == id).map(|run| {
+            run.finished_at = Some(unix_millis());
+            run.duration_ms = Some(elapsed_ms);
+            run.outcome = Some(outcome);
+            run.error = result.as_ref().err().map(|e| e.to_string());
+            run.progress.phase = UpdatePhase::Done;
+            run.progress.current_title = None;
+            run.clone()
+        });
+        drop(state);
+
+        if let Some(run) = finished {
+            self.save_run(&run);
+        }
+
+        result.map(|_| ())
+    }
+
+    /// Saves `run` to the store. The history is only there to look at, so failing to save it doesn't fail the update.
+    fn save_run(&self, run: &UpdateRun) {
+        if let Err(e) = save_run(&self.db, run) {
+            tracing::warn!(id = run.id, error = %e, "couldn't save the update run");
+        }
+    }
+}
+
+/// Records `run` in the store, dropping the one that just fell out of the history.
+fn save_run(db: &Db, run: &UpdateRun) -> ServerResult<()> {
+    let txn = db.store.begin_write()?;
+    {
+        let mut runs = txn.open_table(db.store.update_runs)?;
+        runs.insert(run.id, postcard::to_stdvec(run)?.as_slice())?;
+        if let Some(forgotten) = run.id.checked_sub(HISTORY_LEN as u64) {
+            runs.remove(forgotten)?;
+        }
+    }
+    txn.commit()?;
+
+    Ok(())
+}
+
+/// The last [`HISTORY_LEN`] runs in the store, newest first. Runs that never finished were cut short by a restart.
+fn load_runs(db: &Db) -> ServerResult<VecDeque<UpdateRun>> {
+    let txn = db.store.begin_read()?;
+    let table = match txn.open_table(db.store.update_runs) {
+        Ok(table) => table,
+        Err(CuriosityError::REDBError(redb::Error::TableDoesNotExist(_))) => {
+            return Ok(VecDeque::new())
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut runs = VecDeque::with_capacity(HISTORY_LEN);
+    for entry in table.iter()?.rev().take(HISTORY_LEN) {
+        let (_, run) = entry?;
+        let mut run: UpdateRun = postcard::from_bytes(run.value())?;
+        if run.outcome.is_none() {
+            run.outcome = Some(UpdateOutcome::Failed);
+            run.error = Some("the server stopped before the update finished".to_owned());
+            run.progress.phase = UpdatePhase::Done;
+            run.progress.current_title = None;
+        }
+        runs.push_back(run);
+    }
+
+    Ok(runs)
+}
+
+/// Updates the progress of the run in progress, which is always the newest one.
+fn update_progress(state: &Mutex<UpdaterState>, f: impl FnOnce(&mut UpdateProgress)) {
+    if let Some(run) = state.lock().runs.front_mut() {
+        f(&mut run.progress);
+    }
+}
+
+/// Updates right away, then every six hours.
+pub async fn update_database_periodically(updater: web::Data<Updater>) -> ServerResult<()> {
     let mut interval = actix_web::rt::time::interval(Duration::from_secs(6 * 60 * 60));
     loop {
         interval.tick().await;
-        println!("Updating!");
 
-        if let Err(e) = update_database(db.clone()).await {
-            println!("error during db update: {e}");
+        // failures are logged and kept in the history by the updater; the next tick tries again
+        if let Err(ServerError::UpdateInProgress) = updater.update(UpdateTrigger::Scheduled).await {
+            tracing::info!("skipping scheduled update, one is already running");
         }
     }
 }
 
-pub async fn update_database(db: Db) -> ServerResult<()> {
-    let mirror_bytes = reqwest::get("https://github.com/emily-signet/transcripts-at-the-table-mirror/archive/refs/heads/data.zip").await?.bytes().await?;
+/// Rebuilds the index from the transcript mirror, then drops cached search responses.
+/// Unless `force` is set, does nothing if the archive is the one the index was last built from,
+/// asking the server for it only if it changed since.
+async fn rebuild(
+    db: Db,
+    cache: web::Data<SearchCache>,
+    state: Arc<Mutex<UpdaterState>>,
+    force: bool,
+    cancel: Arc<AtomicBool>,
+) -> ServerResult<UpdateOutcome> {
+    // documents parsed by an older parser are rebuilt even if the archive is the same
+    let previous = if force {
+        None
+    } else {
+        db.store
+            .source(MIRROR_URL)?
+            .filter(|previous| previous.parser_version == PARSER_VERSION)
+    };
+
+    let mut request = reqwest::Client::new().get(MIRROR_URL);
+    if let Some(previous) = previous.as_ref() {
+        if let Some(etag) = previous.etag.as_deref() {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = previous.last_modified.as_deref() {
+            request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+
+    let response = request.send().await?;
+    if response.status() == StatusCode::NOT_MODIFIED {
+        return Ok(UpdateOutcome::Unchanged);
+    }
+
+    let mut response = response.error_for_status()?;
+    let response_header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value: &HeaderValue| value.to_str().ok())
+            .map(str::to_owned)
+    };
+    let etag = response_header(header::ETAG);
+    let last_modified = response_header(header::LAST_MODIFIED);
+
+    // read a chunk at a time, so cancelling doesn't have to wait for the whole archive
+    let mut mirror_bytes = Vec::with_capacity(response.content_length().unwrap_or(0) as usize);
+    while let Some(chunk) = response.chunk().await? {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(CuriosityError::Cancelled.into());
+        }
+        mirror_bytes.extend_from_slice(&chunk);
+    }
 
+    let source = SourceInfo {
+        etag,
+        last_modified,
+        sha256: Sha256::digest(&mirror_bytes).into(),
+        parser_version: PARSER_VERSION,
+    };
+
+    // a new etag doesn't have to mean new contents, e.g. when the archive is regenerated
+    if previous.map(|previous| previous.sha256) == Some(source.sha256) {
+        db.store.set_source(MIRROR_URL, &source)?;
+        return Ok(UpdateOutcome::Unchanged);
+    }
+
+    let span = tracing::Span::current();
     actix_web::rt::task::spawn_blocking(move || {
-        let mut mirror = zip::ZipArchive::new(Cursor::new(mirror_bytes.as_ref()))?;
+        let _entered = span.enter();
+        if cancel.load(Ordering::Relaxed) {
+            return Err(CuriosityError::Cancelled.into());
+        }
+
+        let mut mirror = zip::ZipArchive::new(Cursor::new(mirror_bytes.as_slice()))?;
         let seasons: BTreeMap<SeasonId, Season> = serde_json::from_reader(
             mirror.by_name("transcripts-at-the-table-mirror-data/seasons.json")?,
         )?;
 
-        db.add_documents(seasons.values(), |_, episode| {
-            println!("reading {}", episode.title);
-            let mut f = mirror
-                .by_name(
-                    (Path::new("transcripts-at-the-table-mirror-data/")
-                        .join(episode.download.as_ref().unwrap().plain.clone()))
-                    .to_str()
-                    .unwrap(),
+        let episodes = seasons
+            .values()
+            .flat_map(|season| &season.episodes)
+            .filter(|episode| episode.download.is_some())
+            .count();
+        update_progress(&state, |progress| {
+            progress.phase = UpdatePhase::Indexing;
+            progress.episodes = episodes;
+        });
+
+        let report = db.add_documents(seasons.values(), |_, episode| {
+            if cancel.load(Ordering::Relaxed) {
+                return Err(CuriosityError::Cancelled);
+            }
+
+            update_progress(&state, |progress| {
+                progress.episode += 1;
+                progress.current_title = Some(episode.title.clone());
+            });
+
+            tracing::debug!(slug = %episode.slug, "reading episode");
+            let path = Path::new("transcripts-at-the-table-mirror-data/")
+                .join(episode.download.as_ref().unwrap().plain.clone());
+            let mut f = mirror.by_name(&path.to_string_lossy()).map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("{}: {e}", path.display()),
                 )
-                .map_err(|_| CuriosityError::NotFound)?; // does the error fit? no. will i use it regardless? yes.
+            })?;
 
             let mut out = String::with_capacity((f.compressed_size() * 2) as usize);
             f.read_to_string(&mut out)?;
             Ok(out)
         })?;
 
-        Ok(())
+        let failed = report.failed().count();
+        metrics::INGESTED_EPISODES
+            .with_label_values(&["indexed"])
+            .set(report.indexed as i64);
+        metrics::INGESTED_EPISODES
+            .with_label_values(&["skipped"])
+            .set((report.entries.len() - failed) as i64);
+        metrics::INGESTED_EPISODES
+            .with_label_values(&["failed"])
+            .set(failed as i64);
+        metrics::INGESTION_DURATION
+            .set(report.finished_at.saturating_sub(report.started_at) as f64 / 1000.0);
+
+        tracing::info!(
+            indexed = report.indexed,
+            skipped = report.entries.len() - failed,
+            failed,
+            "ingestion finished"
+        );
+
+        // cache keys carry the index generation so nothing stale would be served, this just frees the memory
+        cache.clear();
+        // episodes that failed should be retried by the next update, not skipped along with an unchanged archive
+        if failed == 0 {
+            db.store.set_source(MIRROR_URL, &source)?;
+        }
+
+        Ok(UpdateOutcome::Succeeded)
     })
     .await
     .unwrap()
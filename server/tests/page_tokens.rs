@@ -0,0 +1,36 @@
+use server::api::page_token::PageToken;
+use server::api::types::SearchRequest;
+use server::config::Config;
+use server::ServerError;
+
+fn token(secret: &[u8]) -> String {
+    let request: SearchRequest = serde_json::from_str(r#"{"query":"dragon"}"#).unwrap();
+    PageToken {
+        generation: 1,
+        after: 0,
+        request: &request,
+    }
+    .encode(secret)
+    .unwrap()
+}
+
+#[test]
+fn generated_page_secrets_outlive_restarts() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let mut before = Config::from_env();
+    before.load_page_secret(dir.path()).unwrap();
+    let mut after = Config::from_env();
+    after.load_page_secret(dir.path()).unwrap();
+
+    assert_eq!(before.page_secret, after.page_secret);
+    assert!(PageToken::decode(&token(&before.page_secret), &after.page_secret, 1).is_ok());
+}
+
+#[test]
+fn tokens_signed_with_another_secret_have_expired() {
+    assert!(matches!(
+        PageToken::decode(&token(b"old secret"), b"new secret", 1),
+        Err(ServerError::ExpiredPageToken)
+    ));
+}
@@ -0,0 +1,56 @@
+use actix_web::{http::StatusCode, test, web, App};
+
+use curiosity::db::Db;
+use server::api::cache::SearchCache;
+use server::config::Config;
+
+async fn search_status(max_query_terms: usize, uri: &str) -> StatusCode {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::new(dir.path()).unwrap();
+
+    let mut config = Config::from_env();
+    config.limits.max_query_terms = max_query_terms;
+
+    let app = test::init_service(
+        App::new()
+            .service(web::scope("/api").service(server::api::search))
+            .app_data(web::Data::new(db))
+            .app_data(web::Data::new(config))
+            .app_data(web::Data::new(SearchCache::new(0))),
+    )
+    .await;
+
+    let response = test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+    response.status()
+}
+
+#[actix_web::test]
+async fn keyword_queries_over_the_term_limit_are_rejected() {
+    assert_eq!(
+        search_status(2, "/api/search?query=alpha+beta+gamma").await,
+        StatusCode::BAD_REQUEST
+    );
+}
+
+#[actix_web::test]
+async fn repeated_keywords_count_once() {
+    assert_eq!(
+        search_status(2, "/api/search?query=alpha+beta+alpha+beta").await,
+        StatusCode::OK
+    );
+}
+
+#[actix_web::test]
+async fn phrase_and_web_queries_over_the_term_limit_are_rejected() {
+    for kind in ["phrase", "web"] {
+        assert_eq!(
+            search_status(
+                2,
+                &format!("/api/search?query=alpha+beta+gamma&kind={kind}")
+            )
+            .await,
+            StatusCode::BAD_REQUEST,
+            "{kind}"
+        );
+    }
+}